@@ -0,0 +1,125 @@
+// Checkpoint files shared by the long-running puzzles: an 8-byte magic naming the puzzle, the format
+// version, then the game state as little-endian integers, then an FNV-1a hash of everything before
+// it. Bump VERSION whenever the header or any puzzle's state layout changes.
+//
+// Included with #[path] by each day that saves checkpoints; not every day uses every function.
+#![allow(dead_code)]
+
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const VERSION: u32 = 1;
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Builds a checkpoint: the header up front, the hash once finished
+pub struct CheckpointWriter {
+    bytes: Vec<u8>,
+}
+
+impl CheckpointWriter {
+    pub fn new(magic: &[u8; 8]) -> CheckpointWriter {
+        let mut bytes = magic.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        CheckpointWriter { bytes }
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    // Length first, then the values
+    pub fn u32s(&mut self, values: &[u32]) {
+        self.u32(values.len() as u32);
+        for &value in values {
+            self.u32(value);
+        }
+    }
+    pub fn finish(mut self) -> Vec<u8> {
+        let hash = fnv1a(&self.bytes);
+        self.bytes.extend_from_slice(&hash.to_le_bytes());
+        self.bytes
+    }
+}
+
+// Reads little-endian integers from a checkpoint, failing at the end of the data
+pub struct CheckpointReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl CheckpointReader<'_> {
+    // Checks the magic, hash and version, leaving the reader at the start of the state. `puzzle`
+    // names the expected kind of checkpoint in errors.
+    pub fn open<'a>(
+        bytes: &'a [u8],
+        magic: &[u8; 8],
+        puzzle: &str,
+    ) -> Result<CheckpointReader<'a>, String> {
+        if bytes.len() < magic.len() + 4 + 8 || !bytes.starts_with(magic) {
+            return Err(format!("Not a {} checkpoint", puzzle));
+        }
+        let (body, hash) = bytes.split_at(bytes.len() - 8);
+        if fnv1a(body) != u64::from_le_bytes(hash.try_into().unwrap()) {
+            return Err("Checkpoint is corrupt (checksum mismatch)".to_string());
+        }
+        let mut reader = CheckpointReader {
+            bytes: &body[magic.len()..],
+        };
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!(
+                "Checkpoint version {} isn't supported (expected {})",
+                version, VERSION
+            ));
+        }
+        Ok(reader)
+    }
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        if self.bytes.len() < n {
+            return Err("Checkpoint is truncated".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn u32s(&mut self) -> Result<Vec<u32>, String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len.checked_mul(4).ok_or("Checkpoint is truncated")?)?;
+        Ok(bytes
+            .chunks(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+    // Fails if anything is left over
+    pub fn finish(self) -> Result<(), String> {
+        if !self.bytes.is_empty() {
+            return Err("Checkpoint has trailing data".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Writes to a temporary file first, so an interrupted save leaves the previous checkpoint intact
+pub fn save(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, bytes)?;
+    fs::rename(&temp, path)
+}
+
+pub fn load<T>(path: &Path, parse: impl Fn(&[u8]) -> Result<T, String>) -> Result<T, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -1,76 +1,1052 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::{Range, RangeInclusive};
+use std::time::{Duration, Instant};
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
 struct Program {
     instructions: Vec<Instruction>,
+    source_lines: Vec<usize>, // 1-based source line of each instruction
 }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Instruction {
-    NOP(isize),
-    ACC(isize),
-    JMP(isize),
+    Nop(isize),
+    Acc(isize),
+    Jmp(isize),
 }
 
-// Generic signature for "process problem state to get an answer"
-type ProcessInputFunc = fn(&Program) -> String;
+// Registers an instruction is allowed to modify. A new opcode needs an arm in from_parts(),
+// execute(), mnemonic(), arg(), takes_offset() and flipped(); the Machine loop itself never looks at
+// individual opcodes.
+struct Registers {
+    ip: isize,
+    acc: isize,
+}
 
-// concrete instance of a ProcessInputFunc implementation
-fn solve_part1(input: &Program) -> String {
+impl Instruction {
+    fn from_parts(op: &str, arg: isize) -> Option<Instruction> {
+        match op {
+            "nop" => Some(Instruction::Nop(arg)),
+            "acc" => Some(Instruction::Acc(arg)),
+            "jmp" => Some(Instruction::Jmp(arg)),
+            _ => None,
+        }
+    }
+    fn execute(&self, regs: &mut Registers) {
+        match *self {
+            Instruction::Nop(_) => regs.ip += 1,
+            Instruction::Acc(arg) => {
+                regs.acc += arg;
+                regs.ip += 1;
+            }
+            Instruction::Jmp(arg) => regs.ip += arg,
+        }
+    }
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Nop(_) => "nop",
+            Instruction::Acc(_) => "acc",
+            Instruction::Jmp(_) => "jmp",
+        }
+    }
+    fn arg(&self) -> isize {
+        match *self {
+            Instruction::Nop(arg) | Instruction::Acc(arg) | Instruction::Jmp(arg) => arg,
+        }
+    }
+    // Whether the argument is a relative address (so the assembler accepts a label for it)
+    fn takes_offset(&self) -> bool {
+        match self {
+            Instruction::Nop(_) | Instruction::Jmp(_) => true,
+            Instruction::Acc(_) => false,
+        }
+    }
+    // The nop<->jmp swap used to repair corrupted programs. Other opcodes can't be flipped.
+    fn flipped(&self) -> Option<Instruction> {
+        match *self {
+            Instruction::Nop(arg) => Some(Instruction::Jmp(arg)),
+            Instruction::Jmp(arg) => Some(Instruction::Nop(arg)),
+            Instruction::Acc(_) => None,
+        }
+    }
+    // Where ip goes after executing this instruction at the given address. Control flow doesn't
+    // depend on acc, so this is all the static analyses need to know about an opcode.
+    fn successor(&self, ip: usize) -> isize {
+        let mut regs = Registers {
+            ip: ip as isize,
+            acc: 0,
+        };
+        self.execute(&mut regs);
+        regs.ip
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:+}", self.mnemonic(), self.arg())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TraceEntry {
+    ip: usize,
+    instruction: Instruction,
+    acc: isize, // accumulator after executing the instruction
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Halted { acc: isize }, // ip landed exactly one past the last instruction
+    Loop { ip: usize, acc: isize }, // ip is about to execute an instruction for the second time
+    OutOfBounds { ip: isize, acc: isize }, // ip landed anywhere else outside the program
+}
+
+struct Machine<'a> {
+    instructions: &'a [Instruction],
+    regs: Registers,
+    executed: Vec<bool>,
+    trace: Option<Vec<TraceEntry>>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(instructions: &'a [Instruction]) -> Machine<'a> {
+        Machine {
+            instructions,
+            regs: Registers { ip: 0, acc: 0 },
+            executed: vec![false; instructions.len()],
+            trace: None,
+        }
+    }
+    fn with_trace(mut self) -> Machine<'a> {
+        self.trace = Some(Vec::new());
+        self
+    }
+    fn acc(&self) -> isize {
+        self.regs.acc
+    }
+    fn ip(&self) -> isize {
+        self.regs.ip
+    }
+    fn trace(&self) -> &[TraceEntry] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+    // Executes the instruction at ip. Returns None if the machine can keep running, or the reason
+    // it stopped. A stopped machine stays stopped; further calls return the same Outcome.
+    fn step(&mut self) -> Option<Outcome> {
+        let ip = self.regs.ip;
+        let acc = self.regs.acc;
+        if ip == self.instructions.len() as isize {
+            return Some(Outcome::Halted { acc });
+        }
+        if ip < 0 || ip > self.instructions.len() as isize {
+            return Some(Outcome::OutOfBounds { ip, acc });
+        }
+        let ip = ip as usize;
+        if self.executed[ip] {
+            return Some(Outcome::Loop { ip, acc });
+        }
+        self.executed[ip] = true;
+        let instruction = self.instructions[ip];
+        instruction.execute(&mut self.regs);
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEntry {
+                ip,
+                instruction,
+                acc: self.regs.acc,
+            });
+        }
+        None
+    }
+    fn run_until_halt_or_loop(&mut self) -> Outcome {
+        loop {
+            if let Some(outcome) = self.step() {
+                return outcome;
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Repair {
+    index: usize,
+    original: Instruction,
+    replacement: Instruction,
+    acc: isize, // accumulator when the repaired program halts
+}
+
+// Finds the single nop/jmp flip that makes the program halt, in O(N).
+// First, walk the control-flow graph backwards from the halt address (one past the last instruction)
+// to find every instruction that already leads to a clean halt. Then run the original program; the
+// first instruction it executes whose flipped successor is in that set is the repair. No instruction
+// on the original (looping) path can reach the halt address, so the repaired run never revisits them.
+fn find_repair(instructions: &[Instruction]) -> Option<Repair> {
+    let len = instructions.len();
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); len + 1];
+    for (ip, inst) in instructions.iter().enumerate() {
+        let next = inst.successor(ip);
+        if next >= 0 && next <= len as isize {
+            predecessors[next as usize].push(ip);
+        }
+    }
+    let mut halts = vec![false; len + 1];
+    halts[len] = true;
+    let mut pending = vec![len];
+    while let Some(ip) = pending.pop() {
+        for &pred in predecessors[ip].iter() {
+            if !halts[pred] {
+                halts[pred] = true;
+                pending.push(pred);
+            }
+        }
+    }
+
+    let mut machine = Machine::new(instructions).with_trace();
+    if let Outcome::Halted { .. } = machine.run_until_halt_or_loop() {
+        return None; // nothing to repair
+    }
+    let (index, original, replacement) = machine.trace().iter().find_map(|t| {
+        let replacement = t.instruction.flipped()?;
+        let next = replacement.successor(t.ip);
+        if next >= 0 && next <= len as isize && halts[next as usize] {
+            Some((t.ip, t.instruction, replacement))
+        } else {
+            None
+        }
+    })?;
+    let mut repaired = instructions.to_vec();
+    repaired[index] = replacement;
+    match Machine::new(&repaired).run_until_halt_or_loop() {
+        Outcome::Halted { acc } => Some(Repair {
+            index,
+            original,
+            replacement,
+            acc,
+        }),
+        outcome => panic!("Repaired program did not halt: {:?}", outcome),
+    }
+}
+
+// One step of a compiled program: a straight-line run of instructions (everything that falls through
+// to the next address, plus the jump that ends the run), collapsed into a single acc update.
+#[derive(Clone, Copy, Debug)]
+struct CompiledOp {
+    acc_delta: isize,
+    next: isize, // absolute address executed after the run
+    end: usize,  // one past the last instruction in the run
+}
+
+// Precomputed form of a program, for running it many times. Every address gets its own op so that
+// jumps into the middle of a run still work; loop detection uses a bitset of executed addresses.
+struct CompiledProgram {
+    instructions: Vec<Instruction>,
+    ops: Vec<CompiledOp>,
+    executed: Vec<u64>,
+}
+
+impl CompiledProgram {
+    fn new(instructions: &[Instruction]) -> CompiledProgram {
+        let len = instructions.len();
+        let mut compiled = CompiledProgram {
+            instructions: instructions.to_vec(),
+            ops: vec![
+                CompiledOp {
+                    acc_delta: 0,
+                    next: 0,
+                    end: 0
+                };
+                len
+            ],
+            executed: vec![0; len.div_ceil(64)],
+        };
+        for ip in (0..len).rev() {
+            compiled.compile_op(ip);
+        }
+        compiled
+    }
+    // Recompiles the op at ip. The op at ip+1 must already be up to date.
+    fn compile_op(&mut self, ip: usize) {
+        let inst = self.instructions[ip];
+        let mut regs = Registers {
+            ip: ip as isize,
+            acc: 0,
+        };
+        inst.execute(&mut regs);
+        self.ops[ip] = if regs.ip == ip as isize + 1 && ip + 1 < self.instructions.len() {
+            let rest = self.ops[ip + 1];
+            CompiledOp {
+                acc_delta: regs.acc + rest.acc_delta,
+                ..rest
+            }
+        } else {
+            CompiledOp {
+                acc_delta: regs.acc,
+                next: regs.ip,
+                end: ip + 1,
+            }
+        };
+    }
+    fn instruction(&self, ip: usize) -> Instruction {
+        self.instructions[ip]
+    }
+    // Replaces one instruction, recompiling only the ops whose runs include it.
+    fn patch(&mut self, index: usize, inst: Instruction) {
+        self.instructions[index] = inst;
+        self.compile_op(index);
+        for ip in (0..index).rev() {
+            if self.instructions[ip].successor(ip) != ip as isize + 1 {
+                break;
+            }
+            self.compile_op(ip);
+        }
+    }
+    // (word index, bit mask) pairs covering a range of addresses in the executed bitset
+    fn bitset_words(range: Range<usize>) -> impl Iterator<Item = (usize, u64)> {
+        let (start, end) = (range.start, range.end);
+        (start / 64..end.div_ceil(64)).map(move |word| {
+            let lo = std::cmp::max(start, word * 64) - word * 64;
+            let hi = std::cmp::min(end, word * 64 + 64) - word * 64;
+            (word, (u64::MAX >> (64 - (hi - lo))) << lo)
+        })
+    }
+    fn first_executed_in(&self, range: Range<usize>) -> Option<usize> {
+        CompiledProgram::bitset_words(range).find_map(|(word, mask)| {
+            let hits = self.executed[word] & mask;
+            if hits != 0 {
+                Some(word * 64 + hits.trailing_zeros() as usize)
+            } else {
+                None
+            }
+        })
+    }
+    fn run(&mut self) -> Outcome {
+        for word in self.executed.iter_mut() {
+            *word = 0;
+        }
+        let len = self.instructions.len() as isize;
+        let mut ip = 0isize;
+        let mut acc = 0isize;
+        loop {
+            if ip == len {
+                return Outcome::Halted { acc };
+            }
+            if ip < 0 || ip > len {
+                return Outcome::OutOfBounds { ip, acc };
+            }
+            let op = self.ops[ip as usize];
+            let start = ip as usize;
+            // An earlier run may have jumped into the middle of this one
+            if let Some(repeat) = self.first_executed_in(start..op.end) {
+                let acc_before_repeat = op.acc_delta - self.ops[repeat].acc_delta;
+                return Outcome::Loop {
+                    ip: repeat,
+                    acc: acc + acc_before_repeat,
+                };
+            }
+            for (word, mask) in CompiledProgram::bitset_words(start..op.end) {
+                self.executed[word] |= mask;
+            }
+            acc += op.acc_delta;
+            ip = op.next;
+        }
+    }
+    // Addresses executed by the last run(), in increasing order
+    fn executed_addresses(&self) -> Vec<usize> {
+        (0..self.instructions.len())
+            .filter(|&ip| self.executed[ip / 64] & (1 << (ip % 64)) != 0)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Edit {
+    index: usize,
+    original: Instruction,
+    replacement: Instruction,
+}
+
+struct RepairOptions {
+    max_edits: usize,
+    arg_range: Option<RangeInclusive<isize>>, // if set, edits may also replace an argument with any value in this range
+    target_acc: Option<isize>, // if set, the repaired program must also halt with this accumulator
+}
+
+#[derive(Debug, PartialEq)]
+struct MultiRepair {
+    edits: Vec<Edit>, // sorted by index
+    acc: isize,
+}
+
+fn edit_candidates(inst: Instruction, options: &RepairOptions) -> Vec<Instruction> {
+    let mut candidates: Vec<Instruction> = inst.flipped().into_iter().collect();
+    if let Some(range) = &options.arg_range {
+        for arg in range.clone().filter(|&arg| arg != inst.arg()) {
+            candidates.push(Instruction::from_parts(inst.mnemonic(), arg).unwrap());
+        }
+    }
+    candidates
+}
+
+// Depth-limited search over edit sets. If the current program fails, any fix has to change at least
+// one instruction the failing run actually executed, so only those are worth editing next.
+fn search_edits(
+    program: &mut CompiledProgram,
+    options: &RepairOptions,
+    edits: &mut Vec<Edit>,
+    edits_left: usize,
+    tried: &mut HashSet<Vec<(usize, Instruction)>>,
+) -> Option<isize> {
+    let mut key: Vec<(usize, Instruction)> =
+        edits.iter().map(|e| (e.index, e.replacement)).collect();
+    key.sort_unstable_by_key(|&(index, _)| index);
+    if !tried.insert(key) {
+        return None; // same edit set, reached in a different order
+    }
+    if let Outcome::Halted { acc } = program.run() {
+        let on_target = match options.target_acc {
+            Some(target) => target == acc,
+            None => true,
+        };
+        if on_target {
+            return Some(acc);
+        }
+    }
+    if edits_left == 0 {
+        return None;
+    }
+    for ip in program.executed_addresses() {
+        if edits.iter().any(|e| e.index == ip) {
+            continue;
+        }
+        let original = program.instruction(ip);
+        for replacement in edit_candidates(original, options) {
+            program.patch(ip, replacement);
+            edits.push(Edit {
+                index: ip,
+                original,
+                replacement,
+            });
+            if let Some(acc) = search_edits(program, options, edits, edits_left - 1, tried) {
+                return Some(acc);
+            }
+            edits.pop();
+        }
+        program.patch(ip, original);
+    }
+    None
+}
+
+// Finds a smallest set of edits that makes the program halt (and optionally hit a target
+// accumulator value), by iterative deepening on the number of edits.
+fn search_repairs(instructions: &[Instruction], options: &RepairOptions) -> Option<MultiRepair> {
+    let mut program = CompiledProgram::new(instructions);
+    for max_edits in 0..=options.max_edits {
+        let mut edits = Vec::new();
+        let mut tried = HashSet::new();
+        if let Some(acc) = search_edits(&mut program, options, &mut edits, max_edits, &mut tried) {
+            edits.sort_unstable_by_key(|e| e.index);
+            return Some(MultiRepair { edits, acc });
+        }
+    }
+    None
+}
+
+fn apply_edits(instructions: &[Instruction], edits: &[Edit]) -> Vec<Instruction> {
+    let mut edited = instructions.to_vec();
+    for edit in edits {
+        edited[edit.index] = edit.replacement;
+    }
+    edited
+}
+
+// Unified diff between two listings of the same length, with `context` unchanged lines around each hunk.
+fn format_diff(before: &[Instruction], after: &[Instruction], context: usize) -> String {
+    assert_eq!(before.len(), after.len());
+    let changed: Vec<usize> = (0..before.len())
+        .filter(|&i| before[i] != after[i])
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in changed.iter() {
+        let start = i.saturating_sub(context);
+        let end = std::cmp::min(i + context + 1, before.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    let mut diff = String::new();
+    for (start, end) in hunks {
+        let len = end - start;
+        diff += &format!("@@ -{},{} +{},{} @@\n", start + 1, len, start + 1, len);
+        for i in start..end {
+            if before[i] == after[i] {
+                diff += &format!(" {}\n", before[i]);
+            } else {
+                diff += &format!("-{}\n+{}\n", before[i], after[i]);
+            }
+        }
+    }
+    diff
+}
+
+struct Analysis {
+    reachable: Vec<bool>,
+    blocks: Vec<Range<usize>>,
+    out_of_range: Vec<usize>, // addresses whose successor is neither an instruction nor the halt address
+    cycles: Vec<(Vec<usize>, bool)>, // each cycle's addresses, and whether the entry point runs into it
+    final_acc: Option<isize>,        // only known if the program halts
+}
+
+// Analyzes the program without running it. Control flow never depends on acc, so every instruction
+// has exactly one successor, and the only path that matters is the one starting at address 0.
+fn analyze(instructions: &[Instruction]) -> Analysis {
+    let len = instructions.len();
+    let successors: Vec<isize> = instructions
+        .iter()
+        .enumerate()
+        .map(|(ip, inst)| inst.successor(ip))
+        .collect();
+    let in_program = |next: isize| next >= 0 && next < len as isize;
+
+    let out_of_range = (0..len)
+        .filter(|&ip| successors[ip] < 0 || successors[ip] > len as isize)
+        .collect();
+
+    let mut is_leader = vec![false; len + 1];
+    is_leader[0] = true;
+    is_leader[len] = true;
+    for ip in 0..len {
+        if successors[ip] != ip as isize + 1 {
+            is_leader[ip + 1] = true;
+            if in_program(successors[ip]) {
+                is_leader[successors[ip] as usize] = true;
+            }
+        }
+    }
+    let leaders: Vec<usize> = (0..=len).filter(|&ip| is_leader[ip]).collect();
+    let blocks: Vec<Range<usize>> = leaders.windows(2).map(|w| w[0]..w[1]).collect();
+
+    // The entry path is a straight walk from block to block until it leaves the program or
+    // repeats itself. Each block adds the sum of its acc arguments on the way through.
+    let mut block_at = vec![usize::MAX; len];
+    for (i, block) in blocks.iter().enumerate() {
+        block_at[block.start] = i;
+    }
+    let acc_deltas: Vec<isize> = blocks
+        .iter()
+        .map(|block| {
+            instructions[block.clone()]
+                .iter()
+                .map(|inst| match inst {
+                    Instruction::Acc(arg) => *arg,
+                    _ => 0,
+                })
+                .sum()
+        })
+        .collect();
+    let mut reachable = vec![false; len];
+    let mut ip = 0isize;
+    let mut acc = 0;
+    while in_program(ip) && !reachable[ip as usize] {
+        let i = block_at[ip as usize];
+        for r in reachable[blocks[i].clone()].iter_mut() {
+            *r = true;
+        }
+        acc += acc_deltas[i];
+        ip = successors[blocks[i].end - 1];
+    }
+    let final_acc = if ip == len as isize { Some(acc) } else { None };
+
+    // Every node has one successor, so cycles are disjoint; walk from each unvisited node and
+    // look for a node visited earlier in the same walk.
+    let mut cycles = Vec::new();
+    let mut walk_id = vec![usize::MAX; len];
+    for start in 0..len {
+        let mut ip = start as isize;
+        let mut walk = Vec::new();
+        while in_program(ip) && walk_id[ip as usize] == usize::MAX {
+            walk_id[ip as usize] = start;
+            walk.push(ip as usize);
+            ip = successors[ip as usize];
+        }
+        if in_program(ip) && walk_id[ip as usize] == start {
+            let cycle_start = walk.iter().position(|&w| w == ip as usize).unwrap();
+            let cycle = walk[cycle_start..].to_vec();
+            let entered = reachable[cycle[0]];
+            cycles.push((cycle, entered));
+        }
+    }
+
+    Analysis {
+        reachable,
+        blocks,
+        out_of_range,
+        cycles,
+        final_acc,
+    }
+}
+
+fn annotated_listing(instructions: &[Instruction], analysis: &Analysis) -> String {
+    let mut listing = format!(
+        "; {} instructions, {} basic blocks\n",
+        instructions.len(),
+        analysis.blocks.len()
+    );
+    match analysis.final_acc {
+        Some(acc) => listing += &format!("; halts with acc={}\n", acc),
+        None => listing += "; never halts\n",
+    }
+    for (cycle, entered) in analysis.cycles.iter() {
+        let addresses: Vec<String> = cycle.iter().map(|ip| ip.to_string()).collect();
+        listing += &format!(
+            "; {} loop: {}\n",
+            if *entered {
+                "guaranteed"
+            } else {
+                "unreachable"
+            },
+            addresses.join(" -> ")
+        );
+    }
+    for (b, block) in analysis.blocks.iter().enumerate() {
+        listing += &format!("block{}:\n", b);
+        for ip in block.clone() {
+            let mut notes = Vec::new();
+            if !analysis.reachable[ip] {
+                notes.push(String::from("unreachable"));
+            }
+            if analysis.out_of_range.contains(&ip) {
+                notes.push(format!(
+                    "jumps out of range to {}",
+                    instructions[ip].successor(ip)
+                ));
+            }
+            if let Some((_, entered)) = analysis.cycles.iter().find(|(c, _)| c.contains(&ip)) {
+                notes.push(String::from(if *entered {
+                    "in infinite loop"
+                } else {
+                    "in loop"
+                }));
+            }
+            let line = format!("{:>5}: {}", ip, instructions[ip]);
+            if notes.is_empty() {
+                listing += &format!("{}\n", line);
+            } else {
+                listing += &format!("{:<18}; {}\n", line, notes.join(", "));
+            }
+        }
+    }
+    listing
+}
+
+// Assembles handheld source code. This is a superset of the puzzle input format:
+// - ';' or '#' starts a comment
+// - "name:" defines a label for the next instruction (or for the halt address, at the end)
+// - nop/jmp can take a label instead of an offset, which is resolved relative to the instruction
+fn assemble(source: &str) -> Result<Program, String> {
+    let label_re = Regex::new(r"^(?P<label>[A-Za-z_]\w*):").unwrap();
+    let inst_re = Regex::new(r"^(?P<op>[a-z]+)\s+(?P<operand>\S+)$").unwrap();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut statements = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut line = line.split([';', '#']).next().unwrap().trim();
+        while let Some(caps) = label_re.captures(line) {
+            let label = caps.name("label").unwrap().as_str();
+            if labels.insert(label, statements.len()).is_some() {
+                return Err(format!("line {}: duplicate label {}", line_number, label));
+            }
+            line = line[caps[0].len()..].trim_start();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let caps = inst_re
+            .captures(line)
+            .ok_or_else(|| format!("line {}: malformed instruction \"{}\"", line_number, line))?;
+        let op = caps.name("op").unwrap().as_str();
+        let operand = caps.name("operand").unwrap().as_str();
+        statements.push((line_number, op, operand));
+    }
+
+    let mut instructions = Vec::with_capacity(statements.len());
+    let source_lines = statements.iter().map(|s| s.0).collect();
+    for (ip, (line_number, op, operand)) in statements.into_iter().enumerate() {
+        let opcode = Instruction::from_parts(op, 0)
+            .ok_or_else(|| format!("line {}: invalid opcode {}", line_number, op))?;
+        let arg = match operand.parse::<isize>() {
+            Ok(arg) => arg,
+            Err(_) if !opcode.takes_offset() => {
+                return Err(format!(
+                    "line {}: {} needs a number, not \"{}\"",
+                    line_number, op, operand
+                ))
+            }
+            Err(_) => {
+                let target = labels
+                    .get(operand)
+                    .ok_or_else(|| format!("line {}: unknown label {}", line_number, operand))?;
+                *target as isize - ip as isize
+            }
+        };
+        instructions.push(Instruction::from_parts(op, arg).unwrap());
+    }
+    Ok(Program {
+        instructions,
+        source_lines,
+    })
+}
+
+// Regenerates assembler source, replacing in-range jmp offsets with labels.
+fn disassemble(instructions: &[Instruction]) -> String {
+    let len = instructions.len();
+    let jump_target = |ip: usize| match instructions[ip] {
+        Instruction::Jmp(_) => Some(instructions[ip].successor(ip))
+            .filter(|&target| target >= 0 && target <= len as isize)
+            .map(|target| target as usize),
+        _ => None,
+    };
+    let mut label_names: Vec<Option<String>> = vec![None; len + 1];
+    let mut targets: Vec<usize> = (0..len).filter_map(jump_target).collect();
+    targets.sort_unstable();
+    targets.dedup();
+    for (n, target) in targets.into_iter().enumerate() {
+        label_names[target] = Some(format!("L{}", n));
+    }
+    let mut source = String::new();
+    for ip in 0..=len {
+        if let Some(label) = &label_names[ip] {
+            source += &format!("{}:\n", label);
+        }
+        if ip == len {
+            break;
+        }
+        match jump_target(ip) {
+            Some(target) => {
+                let label = label_names[target].as_ref().unwrap();
+                source += &format!("    {} {}\n", instructions[ip].mnemonic(), label);
+            }
+            None => source += &format!("    {}\n", instructions[ip]),
+        }
+    }
+    source
+}
+
+// Graphviz export of the control-flow graph, one node per basic block.
+fn control_flow_dot(instructions: &[Instruction]) -> String {
+    let len = instructions.len();
+    let analysis = analyze(instructions);
+    let block_of = |ip: isize| -> String {
+        if ip == len as isize {
+            String::from("halt")
+        } else if ip < 0 || ip > len as isize {
+            String::from("out_of_range")
+        } else {
+            let b = analysis
+                .blocks
+                .iter()
+                .position(|block| block.contains(&(ip as usize)))
+                .unwrap();
+            format!("block{}", b)
+        }
+    };
+    let mut dot =
+        String::from("digraph program {\n    node [shape=box, fontname=\"monospace\"];\n");
+    dot += "    halt [shape=doublecircle];\n";
+    if !analysis.out_of_range.is_empty() {
+        dot += "    out_of_range [shape=octagon, color=red];\n";
+    }
+    for (b, block) in analysis.blocks.iter().enumerate() {
+        let lines: Vec<String> = block
+            .clone()
+            .map(|ip| format!("{}: {}\\l", ip, instructions[ip]))
+            .collect();
+        let style = if analysis.reachable[block.start] {
+            ""
+        } else {
+            ", style=dashed, color=gray"
+        };
+        dot += &format!("    block{} [label=\"{}\"{}];\n", b, lines.concat(), style);
+    }
+    for (b, block) in analysis.blocks.iter().enumerate() {
+        let last = block.end - 1;
+        let next = instructions[last].successor(last);
+        dot += &format!("    block{} -> {};\n", b, block_of(next));
+    }
+    dot += "}\n";
+    dot
+}
+
+// Random program for tests and benchmarks. With `long_running`, jumps only go forward except for a
+// final jump back to the start, so a run executes most of the program before it loops.
+fn generate_program(len: usize, long_running: bool, seed: u64) -> Vec<Instruction> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut instructions: Vec<Instruction> = (0..len)
+        .map(|ip| {
+            let max_jump = if long_running { 4 } else { len as isize };
+            let min_jump = if long_running { 1 } else { -(ip as isize) - 2 };
+            match rng.gen_range(0, 4) {
+                0 | 1 => Instruction::Acc(rng.gen_range(-50, 51)),
+                2 => Instruction::Nop(rng.gen_range(min_jump, max_jump)),
+                _ => Instruction::Jmp(rng.gen_range(min_jump, max_jump)),
+            }
+        })
+        .collect();
+    if long_running && len > 0 {
+        instructions[len - 1] = Instruction::Jmp(-(len as isize - 1));
+    }
+    instructions
+}
+
+// The original loop detection, with a HashSet of every ip executed so far. Only kept as the
+// benchmark baseline; the outcome is the same as Machine's.
+fn run_with_hashset(instructions: &[Instruction]) -> Outcome {
     let mut acc = 0;
     let mut executed = HashSet::new();
     let mut ip: isize = 0;
     loop {
-        if executed.contains(&ip) {
-            break;
+        if ip == instructions.len() as isize {
+            return Outcome::Halted { acc };
+        }
+        if ip < 0 || ip > instructions.len() as isize {
+            return Outcome::OutOfBounds { ip, acc };
         }
-        executed.insert(ip);
-        match input.instructions[ip as usize] {
-            Instruction::NOP(_) => ip += 1,
-            Instruction::ACC(arg) => {
+        if !executed.insert(ip) {
+            return Outcome::Loop {
+                ip: ip as usize,
+                acc,
+            };
+        }
+        match instructions[ip as usize] {
+            Instruction::Nop(_) => ip += 1,
+            Instruction::Acc(arg) => {
                 acc += arg;
                 ip += 1
             }
-            Instruction::JMP(arg) => ip += arg,
+            Instruction::Jmp(arg) => ip += arg,
         }
     }
-    acc.to_string()
 }
 
-fn solve_part2(input: &Program) -> String {
-    for ip_switch in 0..input.instructions.len() {
-        let mut acc = 0;
-        let mut executed = HashSet::new();
-        let mut ip: isize = 0;
-        loop {
-            if ip as usize >= input.instructions.len() {
-                return acc.to_string();
-            }
-            if executed.contains(&ip) {
-                break; // infinite loop
-            }
-            executed.insert(ip);
-            let mut inst = &input.instructions[ip as usize];
-            let mut _inst_swap = Instruction::NOP(0);
-            if ip as usize == ip_switch {
-                _inst_swap = match *inst {
-                    Instruction::NOP(arg) => Instruction::JMP(arg),
-                    Instruction::JMP(arg) => Instruction::NOP(arg),
-                    Instruction::ACC(arg) => Instruction::ACC(arg),
-                };
-                inst = &_inst_swap;
+fn load_program(filename: &str) -> Program {
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    assemble(&contents).unwrap_or_else(|err| panic!("{}: {}", filename, err))
+}
+
+enum Breakpoint {
+    Address(usize),
+    Line { line: usize, ip: usize },
+    Opcode(String),
+}
+
+impl Breakpoint {
+    // Source lines map to the first instruction on or after that line, since labels and comments
+    // take up lines of their own
+    fn parse(arg: &str, program: &Program) -> Result<Breakpoint, String> {
+        if let Some(line) = arg.strip_prefix("line:") {
+            let line: usize = line
+                .parse()
+                .map_err(|_| format!("bad line number {}", line))?;
+            let ip = program
+                .source_lines
+                .iter()
+                .position(|&l| l >= line)
+                .ok_or_else(|| format!("no instruction on or after line {}", line))?;
+            return Ok(Breakpoint::Line { line, ip });
+        }
+        Ok(match arg.parse::<usize>() {
+            Ok(ip) => Breakpoint::Address(ip),
+            Err(_) => Breakpoint::Opcode(arg.to_string()),
+        })
+    }
+    fn hit(&self, ip: usize, inst: &Instruction) -> bool {
+        match self {
+            Breakpoint::Address(address) | Breakpoint::Line { ip: address, .. } => *address == ip,
+            Breakpoint::Opcode(op) => op == inst.mnemonic(),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Address(ip) => write!(f, "{}", ip),
+            Breakpoint::Line { line, ip } => write!(f, "line {} ({})", line, ip),
+            Breakpoint::Opcode(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+const DEBUGGER_HELP: &str = "\
+step [N]       execute N instructions (default 1)
+continue       run until a breakpoint, watched acc change, or the program stops
+run            restart from the beginning and continue
+reset          restart from the beginning
+break BP       stop before executing the instruction at address BP, at source line:BP, or any
+               BP opcode
+delete [BP]    remove one breakpoint, or all of them
+watch          stop whenever acc changes (\"unwatch\" to turn off)
+flip N         swap nop/jmp at address N, then run again
+print          show ip and acc
+list [N]       show N instructions around ip (default 5)
+trace          show every instruction executed so far
+quit";
+
+fn describe_outcome(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Halted { acc } => format!("halted, acc={}", acc),
+        Outcome::Loop { ip, acc } => format!("loop detected at {}, acc={}", ip, acc),
+        Outcome::OutOfBounds { ip, acc } => format!("jumped out of bounds to {}, acc={}", ip, acc),
+    }
+}
+
+// Interactive debugger. Commands are read one per line from `input` (see DEBUGGER_HELP), so the
+// same code serves a terminal session and a scripted test.
+fn run_debugger<R: BufRead, W: Write>(
+    source: &Program,
+    input: R,
+    output: &mut W,
+) -> io::Result<()> {
+    let mut program = source.instructions.to_vec();
+    let mut breakpoints: Vec<Breakpoint> = Vec::new();
+    let mut watch = false;
+    let mut machine = Machine::new(&program).with_trace();
+    let mut paused_at = None; // where the last step or continue stopped, if the machine has run
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = words.get(1).copied();
+        let command = match words.first() {
+            Some(command) => *command,
+            None => continue,
+        };
+        let mut run_to_break = false;
+        match command {
+            "help" | "h" => writeln!(output, "{}", DEBUGGER_HELP)?,
+            "quit" | "q" => break,
+            "print" | "p" => writeln!(output, "ip={} acc={}", machine.ip(), machine.acc())?,
+            "step" | "s" => {
+                let count = arg.map_or(1, |n| n.parse().unwrap_or(1));
+                for _ in 0..count {
+                    if let Some(outcome) = machine.step() {
+                        writeln!(output, "{}", describe_outcome(&outcome))?;
+                        break;
+                    }
+                }
+                if let Some(t) = machine.trace().last() {
+                    writeln!(output, "{}: {} -> acc={}", t.ip, t.instruction, t.acc)?;
+                }
+                paused_at = Some(machine.ip());
+            }
+            "continue" | "c" => run_to_break = true,
+            "run" | "r" | "reset" => {
+                machine = Machine::new(&program).with_trace();
+                paused_at = None;
+                run_to_break = command != "reset";
+            }
+            "break" | "b" => match arg {
+                Some(arg) => match Breakpoint::parse(arg, source) {
+                    Ok(bp) => {
+                        breakpoints.push(bp);
+                        let bp = breakpoints.last().unwrap();
+                        writeln!(output, "breakpoint {} at {}", breakpoints.len(), bp)?;
+                    }
+                    Err(err) => writeln!(output, "{}", err)?,
+                },
+                None => {
+                    for (n, bp) in breakpoints.iter().enumerate() {
+                        writeln!(output, "breakpoint {} at {}", n + 1, bp)?;
+                    }
+                }
+            },
+            "delete" | "d" => match arg.and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n >= 1 && n <= breakpoints.len() => {
+                    breakpoints.remove(n - 1);
+                }
+                Some(n) => writeln!(output, "no breakpoint {}", n)?,
+                None => breakpoints.clear(),
+            },
+            "watch" | "w" => watch = true,
+            "unwatch" => watch = false,
+            "flip" | "f" => match arg.and_then(|n| n.parse::<usize>().ok()) {
+                Some(ip) if ip < program.len() && program[ip].flipped().is_some() => {
+                    drop(machine);
+                    let original = program[ip];
+                    program[ip] = original.flipped().unwrap();
+                    writeln!(output, "{}: {} -> {}", ip, original, program[ip])?;
+                    machine = Machine::new(&program).with_trace();
+                    paused_at = None;
+                    run_to_break = true;
+                }
+                _ => writeln!(output, "can only flip a nop or jmp instruction")?,
+            },
+            "list" | "l" => {
+                let radius = arg.map_or(5, |n| n.parse().unwrap_or(5)) as isize;
+                for ip in machine.ip() - radius..=machine.ip() + radius {
+                    if ip >= 0 && ip < program.len() as isize {
+                        let cursor = if ip == machine.ip() { "=>" } else { "  " };
+                        writeln!(output, "{} {:>4}: {}", cursor, ip, program[ip as usize])?;
+                    }
+                }
+            }
+            "trace" | "t" => {
+                for t in machine.trace() {
+                    writeln!(output, "{}: {} -> acc={}", t.ip, t.instruction, t.acc)?;
+                }
             }
-            match inst {
-                Instruction::NOP(_) => ip += 1,
-                Instruction::ACC(arg) => {
-                    acc += arg;
-                    ip += 1
+            _ => writeln!(output, "unknown command {} (try \"help\")", command)?,
+        }
+        if run_to_break {
+            // Continuing from where we're paused runs past any breakpoint there, rather than
+            // stopping again without making progress. A fresh run checks the first instruction too.
+            let mut resuming = paused_at == Some(machine.ip());
+            loop {
+                let ip = machine.ip();
+                if !resuming && ip >= 0 && (ip as usize) < program.len() {
+                    let inst = &program[ip as usize];
+                    if let Some(n) = breakpoints.iter().position(|bp| bp.hit(ip as usize, inst)) {
+                        writeln!(output, "breakpoint {} at {}: {}", n + 1, ip, inst)?;
+                        break;
+                    }
+                }
+                resuming = false;
+                let acc = machine.acc();
+                if let Some(outcome) = machine.step() {
+                    writeln!(output, "{}", describe_outcome(&outcome))?;
+                    break;
+                }
+                if watch && machine.acc() != acc {
+                    writeln!(output, "acc changed {} -> {} at {}", acc, machine.acc(), ip)?;
+                    break;
                 }
-                Instruction::JMP(arg) => ip += arg,
             }
+            paused_at = Some(machine.ip());
         }
     }
-    String::from("No program terminated")
+    Ok(())
+}
+
+// Generic signature for "process problem state to get an answer"
+type ProcessInputFunc = fn(&Program) -> String;
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Program) -> String {
+    let mut machine = Machine::new(&input.instructions);
+    while machine.step().is_none() {}
+    machine.acc().to_string()
+}
+
+fn solve_part2(input: &Program) -> String {
+    match find_repair(&input.instructions) {
+        Some(repair) => repair.acc.to_string(),
+        None => String::from("No program terminated"),
+    }
 }
 
 // Day-specific code to process text data into custom problem state
@@ -86,16 +1062,15 @@ fn parse_input_text(input: &str) -> Program {
             .as_str()
             .parse::<isize>()
             .unwrap();
-        instructions.push(match op {
-            "nop" => Instruction::NOP(arg),
-            "acc" => Instruction::ACC(arg),
-            "jmp" => Instruction::JMP(arg),
-            _ => {
-                panic!("Invalid opcode {}", op);
-            }
-        })
+        instructions.push(
+            Instruction::from_parts(op, arg).unwrap_or_else(|| panic!("Invalid opcode {}", op)),
+        )
+    }
+    let source_lines = (1..=instructions.len()).collect();
+    Program {
+        instructions,
+        source_lines,
     }
-    Program { instructions }
 }
 
 fn process_text(input_text: &str, processor: ProcessInputFunc, expected: &str) -> String {
@@ -111,6 +1086,117 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Usage: day08 assemble|disassemble|dot [FILE]
+fn run_listing_tool(command: &str, args: &[String]) {
+    let filename = args.first().map_or("inputs/input08.txt", String::as_str);
+    let program = load_program(filename);
+    match command {
+        "assemble" => {
+            for inst in program.instructions.iter() {
+                println!("{}", inst);
+            }
+        }
+        "disassemble" => print!("{}", disassemble(&program.instructions)),
+        "dot" => print!("{}", control_flow_dot(&program.instructions)),
+        _ => unreachable!(),
+    }
+}
+
+// Usage: day08 debug [FILE], then type commands (or "help")
+fn run_debug_tool(args: &[String]) {
+    let filename = args.first().map_or("inputs/input08.txt", String::as_str);
+    let program = load_program(filename);
+    let stdin = io::stdin();
+    run_debugger(&program, stdin.lock(), &mut io::stdout()).unwrap();
+}
+
+// Usage: day08 bench [LEN] [RUNS]
+fn run_bench_tool(args: &[String]) {
+    let len = args.first().map_or(100_000, |n| n.parse().unwrap());
+    let runs = args.get(1).map_or(500, |n| n.parse().unwrap());
+    let instructions = generate_program(len, true, 2020);
+    let time = |run: &mut dyn FnMut() -> Outcome| {
+        let start = Instant::now();
+        let mut outcome = None;
+        for _ in 0..runs {
+            outcome = Some(run());
+        }
+        (outcome.unwrap(), start.elapsed())
+    };
+
+    let (baseline, baseline_time) = time(&mut || run_with_hashset(&instructions));
+    let (interpreted, interpreted_time) =
+        time(&mut || Machine::new(&instructions).run_until_halt_or_loop());
+    let start = Instant::now();
+    let mut compiled_program = CompiledProgram::new(&instructions);
+    let compile_time = start.elapsed();
+    let (compiled, compiled_time) = time(&mut || compiled_program.run());
+
+    assert_eq!(baseline, interpreted);
+    assert_eq!(baseline, compiled);
+    println!("{} instructions, {} runs: {:?}", len, runs, compiled);
+    println!("HashSet:     {:>10.3?}", baseline_time);
+    println!("interpreted: {:>10.3?}", interpreted_time);
+    println!(
+        "compiled:    {:>10.3?} (+{:.3?} to compile)",
+        compiled_time, compile_time
+    );
+    let speedup = |time: Duration| time.as_secs_f64() / compiled_time.as_secs_f64();
+    println!(
+        "speedup:     {:>9.1}x over HashSet, {:.1}x over interpreted",
+        speedup(baseline_time),
+        speedup(interpreted_time)
+    );
+}
+
+// Usage: day08 analyze [FILE]
+fn run_analyze_tool(args: &[String]) {
+    let filename = args.first().map_or("inputs/input08.txt", String::as_str);
+    let program = load_program(filename);
+    let analysis = analyze(&program.instructions);
+    print!("{}", annotated_listing(&program.instructions, &analysis));
+}
+
+// Usage: day08 repair [--max-edits N] [--target-acc N] [--arg-range MIN..MAX] [FILE]
+fn run_repair_tool(args: &[String]) {
+    let mut options = RepairOptions {
+        max_edits: 1,
+        arg_range: None,
+        target_acc: None,
+    };
+    let mut filename = "inputs/input08.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--max-edits" => options.max_edits = value().parse().unwrap(),
+            "--target-acc" => options.target_acc = Some(value().parse().unwrap()),
+            "--arg-range" => {
+                let (min, max) = value().split_once("..").expect("Expected MIN..MAX");
+                options.arg_range = Some(min.parse().unwrap()..=max.parse().unwrap());
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let program = load_program(filename);
+    match search_repairs(&program.instructions, &options) {
+        Some(repair) => {
+            println!(
+                "Halts with acc={} after {} edit(s):",
+                repair.acc,
+                repair.edits.len()
+            );
+            let edited = apply_edits(&program.instructions, &repair.edits);
+            print!("{}", format_diff(&program.instructions, &edited, 3));
+        }
+        None => println!("No repair found with at most {} edit(s)", options.max_edits),
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 nop +0
 acc +1
@@ -132,7 +1218,294 @@ fn test_day08_part2() {
     process_text(_TEST_INPUT1, solve_part2, "8");
 }
 
+#[test]
+fn test_day08_machine() {
+    let program = parse_input_text(_TEST_INPUT1);
+    let mut machine = Machine::new(&program.instructions).with_trace();
+    assert_eq!(None, machine.step());
+    assert_eq!(None, machine.step());
+    assert_eq!(1, machine.acc());
+    assert_eq!(
+        Outcome::Loop { ip: 1, acc: 5 },
+        machine.run_until_halt_or_loop()
+    );
+    let ips: Vec<usize> = machine.trace().iter().map(|t| t.ip).collect();
+    assert_eq!(vec![0, 1, 2, 6, 7, 3, 4], ips);
+
+    let program = parse_input_text("acc +2\njmp -5\nacc +1");
+    assert_eq!(
+        Outcome::OutOfBounds { ip: -4, acc: 2 },
+        Machine::new(&program.instructions).run_until_halt_or_loop()
+    );
+    let program = parse_input_text("acc +2\njmp +2\nacc +1");
+    assert_eq!(
+        Outcome::Halted { acc: 2 },
+        Machine::new(&program.instructions).run_until_halt_or_loop()
+    );
+}
+
+#[test]
+fn test_day08_find_repair() {
+    let program = parse_input_text(_TEST_INPUT1);
+    assert_eq!(
+        Some(Repair {
+            index: 7,
+            original: Instruction::Jmp(-4),
+            replacement: Instruction::Nop(-4),
+            acc: 8,
+        }),
+        find_repair(&program.instructions)
+    );
+    assert_eq!("jmp -4", program.instructions[7].to_string());
+    // already halts
+    let program = parse_input_text("acc +2\njmp +2\nacc +1");
+    assert_eq!(None, find_repair(&program.instructions));
+    // no single flip helps
+    let program = parse_input_text("jmp +0\njmp -1");
+    assert_eq!(None, find_repair(&program.instructions));
+}
+
+#[test]
+fn test_day08_search_repairs() {
+    let program = parse_input_text(_TEST_INPUT1);
+    let mut options = RepairOptions {
+        max_edits: 2,
+        arg_range: None,
+        target_acc: None,
+    };
+    let repair = search_repairs(&program.instructions, &options).unwrap();
+    assert_eq!(8, repair.acc);
+    assert_eq!(1, repair.edits.len());
+    assert_eq!(7, repair.edits[0].index);
+    assert_eq!(
+        "@@ -6,4 +6,4 @@\n acc -99\n acc +1\n-jmp -4\n+nop -4\n acc +6\n",
+        format_diff(
+            &program.instructions,
+            &apply_edits(&program.instructions, &repair.edits),
+            2
+        )
+    );
+
+    // Hitting a specific accumulator needs an argument change on top of the flip
+    options.target_acc = Some(9);
+    assert_eq!(None, search_repairs(&program.instructions, &options));
+    options.arg_range = Some(-3..=3);
+    let repair = search_repairs(&program.instructions, &options).unwrap();
+    assert_eq!(9, repair.acc);
+    assert_eq!(2, repair.edits.len());
+
+    // Two independent faults
+    let program = parse_input_text("nop +0\njmp +0\njmp -2\nacc +1");
+    options.target_acc = None;
+    options.arg_range = None;
+    options.max_edits = 1;
+    assert_eq!(None, search_repairs(&program.instructions, &options));
+    options.max_edits = 3;
+    let repair = search_repairs(&program.instructions, &options).unwrap();
+    let indices: Vec<usize> = repair.edits.iter().map(|e| e.index).collect();
+    assert_eq!(vec![1, 2], indices);
+    assert_eq!(1, repair.acc);
+}
+
+#[test]
+fn test_day08_analyze() {
+    let program = parse_input_text(_TEST_INPUT1);
+    let analysis = analyze(&program.instructions);
+    assert_eq!(None, analysis.final_acc);
+    assert_eq!(vec![(vec![1, 2, 6, 7, 3, 4], true)], analysis.cycles);
+    assert_eq!(vec![0..1, 1..3, 3..5, 5..6, 6..8, 8..9], analysis.blocks);
+    assert!(!analysis.reachable[5] && !analysis.reachable[8]);
+    assert!(analysis.out_of_range.is_empty());
+
+    let program = parse_input_text("acc +2\njmp +2\njmp -9\nacc +5\nnop +0");
+    let analysis = analyze(&program.instructions);
+    assert_eq!(Some(7), analysis.final_acc);
+    assert_eq!(vec![2], analysis.out_of_range);
+    assert_eq!(
+        "\
+; 5 instructions, 3 basic blocks
+; halts with acc=7
+block0:
+    0: acc +2
+    1: jmp +2
+block1:
+    2: jmp -9     ; unreachable, jumps out of range to -7
+block2:
+    3: acc +5
+    4: nop +0
+",
+        annotated_listing(&program.instructions, &analysis)
+    );
+}
+
+#[test]
+fn test_day08_assemble() {
+    let source = "\
+; the example program, with labels instead of offsets
+    nop +0
+loop:
+    acc +1      # enters the loop
+    jmp skip
+back: acc +3
+    jmp loop
+    acc -99
+skip:
+    acc +1
+    jmp back
+    acc +6
+";
+    let program = assemble(source).unwrap();
+    assert_eq!(
+        parse_input_text(_TEST_INPUT1).instructions,
+        program.instructions
+    );
+    let listing = disassemble(&program.instructions);
+    assert_eq!(
+        "    nop +0\nL0:\n    acc +1\n    jmp L2\nL1:\n    acc +3\n    jmp L0\n    acc -99\nL2:\n    acc +1\n    jmp L1\n    acc +6\n",
+        listing
+    );
+    assert_eq!(
+        program.instructions,
+        assemble(&listing).unwrap().instructions
+    );
+
+    let program = assemble("jmp end\njmp -9\nend:").unwrap();
+    assert_eq!(
+        vec![Instruction::Jmp(2), Instruction::Jmp(-9)],
+        program.instructions
+    );
+    let dot = control_flow_dot(&program.instructions);
+    assert!(dot.contains("block0 -> halt;"));
+    assert!(dot.contains("block1 -> out_of_range;"));
+    assert!(dot.contains("style=dashed"));
+
+    assert_eq!(
+        Err(String::from("line 1: unknown label nowhere")),
+        assemble("jmp nowhere").map(|_| ())
+    );
+    assert_eq!(
+        Err(String::from("line 2: duplicate label a")),
+        assemble("a: nop +0\na: nop +0").map(|_| ())
+    );
+    assert_eq!(
+        Err(String::from("line 2: acc needs a number, not \"a\"")),
+        assemble("a:\nacc a").map(|_| ())
+    );
+    assert_eq!(
+        Err(String::from("line 1: invalid opcode mul")),
+        assemble("mul +2").map(|_| ())
+    );
+}
+
+#[test]
+fn test_day08_debugger() {
+    let program = parse_input_text(_TEST_INPUT1);
+    let script = "\
+step 2
+print
+break jmp
+continue
+watch
+continue
+unwatch
+delete
+continue
+flip 7
+break 6
+run
+print
+flip 3
+list 1
+";
+    let mut output = Vec::new();
+    run_debugger(&program, script.as_bytes(), &mut output).unwrap();
+    assert_eq!(
+        "\
+1: acc +1 -> acc=1
+ip=2 acc=1
+breakpoint 1 at jmp
+breakpoint 1 at 7: jmp -4
+acc changed 2 -> 5 at 3
+loop detected at 1, acc=5
+7: jmp -4 -> nop -4
+halted, acc=8
+breakpoint 1 at 6
+breakpoint 1 at 6: acc +1
+ip=6 acc=1
+can only flip a nop or jmp instruction
+      5: acc -99
+=>    6: acc +1
+      7: nop -4
+",
+        String::from_utf8(output).unwrap()
+    );
+
+    // A fresh run stops at address 0, and line breakpoints skip past labels and comments
+    let program =
+        assemble("; one acc\nstart:\n    acc +1\nskip:\n    jmp +2\n    acc -99\n    nop +0\n")
+            .unwrap();
+    let script = "\
+break 0
+run
+continue
+delete
+break line:4
+break line:9
+run
+";
+    let mut output = Vec::new();
+    run_debugger(&program, script.as_bytes(), &mut output).unwrap();
+    assert_eq!(
+        "\
+breakpoint 1 at 0
+breakpoint 1 at 0: acc +1
+halted, acc=1
+breakpoint 1 at line 4 (1)
+no instruction on or after line 9
+breakpoint 1 at 1: jmp +2
+",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[test]
+fn test_day08_compiled_program() {
+    let program = parse_input_text(_TEST_INPUT1);
+    let mut compiled = CompiledProgram::new(&program.instructions);
+    assert_eq!(Outcome::Loop { ip: 1, acc: 5 }, compiled.run());
+    assert_eq!(vec![0, 1, 2, 3, 4, 6, 7], compiled.executed_addresses());
+    compiled.patch(7, Instruction::Nop(-4));
+    assert_eq!(Outcome::Halted { acc: 8 }, compiled.run());
+
+    // Must agree with the interpreter and the HashSet baseline everywhere, including after patches
+    for seed in 0..200 {
+        let mut instructions = generate_program(1 + seed as usize % 40, seed % 3 == 0, seed);
+        let mut compiled = CompiledProgram::new(&instructions);
+        for ip in 0..instructions.len() {
+            let outcome = Machine::new(&instructions).run_until_halt_or_loop();
+            assert_eq!(outcome, compiled.run());
+            assert_eq!(outcome, run_with_hashset(&instructions));
+            if let Some(flipped) = instructions[ip].flipped() {
+                instructions[ip] = flipped;
+                compiled.patch(ip, flipped);
+            }
+        }
+    }
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some(command @ ("assemble" | "disassemble" | "dot")) => {
+            return run_listing_tool(command, &args[1..])
+        }
+        Some("bench") => return run_bench_tool(&args[1..]),
+        Some("debug") => return run_debug_tool(&args[1..]),
+        Some("analyze") => return run_analyze_tool(&args[1..]),
+        Some("repair") => return run_repair_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input08.txt", solve_part1, "1217")
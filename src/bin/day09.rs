@@ -1,4 +1,9 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead};
+use std::ops::Range;
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
 struct Input {
@@ -6,52 +11,204 @@ struct Input {
 }
 
 // Generic signature for "process problem state to get an answer"
-type ProcessInputFunc = fn(&Input, usize, u64) -> String;
-
-fn is_valid_number(n: u64, history: &[u64]) -> bool {
-    let len = history.len();
-    for ia in 0..len - 1 {
-        let a = history[ia];
-        for b in history[ia + 1..len].iter() {
-            if a + b == n {
-                return true;
+type ProcessInputFunc = fn(&Input, usize, u64) -> Option<u64>;
+
+// Checks each number against the previous `window_size` numbers as it arrives. Instead of rescanning
+// every pair in the window, it keeps a count of every pairwise sum in the window, so each new number
+// costs O(window) to add/remove and O(1) to check.
+struct XmasValidator {
+    window_size: usize,
+    window: VecDeque<u64>,
+    pair_sums: HashMap<u64, usize>,
+}
+
+impl XmasValidator {
+    fn new(window_size: usize) -> XmasValidator {
+        assert!(window_size > 0, "The window must hold at least one number");
+        XmasValidator {
+            window_size,
+            window: VecDeque::with_capacity(window_size),
+            pair_sums: HashMap::with_capacity(window_size * window_size / 2),
+        }
+    }
+    // Returns false if n is not the sum of two numbers in the current window. Numbers in the preamble
+    // (before the window is full) are always valid.
+    fn push(&mut self, n: u64) -> bool {
+        let is_valid = self.window.len() < self.window_size || self.pair_sums.contains_key(&n);
+        if self.window.len() == self.window_size {
+            let oldest = self.window.pop_front().unwrap();
+            for &other in self.window.iter() {
+                // Sums that leave the window entirely are dropped, so a long stream doesn't keep
+                // every sum it has ever seen
+                let sum = oldest + other;
+                let count = self.pair_sums.get_mut(&sum).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    self.pair_sums.remove(&sum);
+                }
             }
         }
+        for &other in self.window.iter() {
+            *self.pair_sums.entry(n + other).or_default() += 1;
+        }
+        self.window.push_back(n);
+        is_valid
     }
-    false
 }
 
-// concrete instance of a ProcessInputFunc implementation
-fn solve_part1(input: &Input, history_size: usize, _: u64) -> String {
-    // is there a better solution than O(N^2)? who cares!
-    for i in history_size..input.numbers.len() {
-        let history = &input.numbers[i - history_size..i];
-        let next = input.numbers[i];
-        if !is_valid_number(next, history) {
-            return next.to_string();
+// Two-pointer scan for a run of at least two consecutive numbers that adds up to `target_sum`. Only the
+// current run is kept, so this also works on a stream. Numbers are unsigned, so the run only ever
+// needs to grow at the end or shrink at the start.
+struct ContiguousSumFinder {
+    target_sum: u64,
+    run: VecDeque<u64>,
+    run_start: usize,
+    sum: u64,
+}
+
+impl ContiguousSumFinder {
+    fn new(target_sum: u64) -> ContiguousSumFinder {
+        ContiguousSumFinder {
+            target_sum,
+            run: VecDeque::new(),
+            run_start: 0,
+            sum: 0,
         }
     }
-    String::from("All are valid!")
-}
-
-fn solve_part2(input: &Input, _: usize, target_sum: u64) -> String {
-    for i0 in 0..input.numbers.len() {
-        let mut sum = input.numbers[i0];
-        let mut min = sum;
-        let mut max = sum;
-        for i1 in i0 + 1..input.numbers.len() {
-            min = std::cmp::min(min, input.numbers[i1]);
-            max = std::cmp::max(max, input.numbers[i1]);
-            sum += input.numbers[i1];
-            if sum == target_sum {
-                return (min + max).to_string();
+    // Feeds the next number. Returns the index range of the run once the sum is hit.
+    fn push(&mut self, n: u64) -> Option<Range<usize>> {
+        self.run.push_back(n);
+        self.sum += n;
+        while self.sum > self.target_sum && self.run.len() > 1 {
+            self.sum -= self.run.pop_front().unwrap();
+            self.run_start += 1;
+        }
+        if self.sum == self.target_sum && self.run.len() > 1 {
+            Some(self.run_start..self.run_start + self.run.len())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct ContiguousRun {
+    start: usize, // inclusive
+    end: usize,   // inclusive
+    min: u64,
+    max: u64,
+}
+
+impl ContiguousRun {
+    fn weakness(&self) -> u64 {
+        self.min + self.max
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Anomaly {
+    index: usize,
+    value: u64,
+    runs: Vec<ContiguousRun>, // every run of 2+ numbers that adds up to value
+}
+
+#[derive(Debug, PartialEq)]
+struct AnomalyReport {
+    anomalies: Vec<Anomaly>,
+}
+
+impl fmt::Display for AnomalyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.anomalies.is_empty() {
+            return writeln!(f, "All numbers are valid");
+        }
+        for anomaly in self.anomalies.iter() {
+            writeln!(f, "#{}: {} is invalid", anomaly.index, anomaly.value)?;
+            if anomaly.runs.is_empty() {
+                writeln!(f, "    no contiguous run adds up to it")?;
+            }
+            for run in anomaly.runs.iter() {
+                writeln!(
+                    f,
+                    "    #{}..=#{} adds up to it: min {}, max {}, min+max {}",
+                    run.start,
+                    run.end,
+                    run.min,
+                    run.max,
+                    run.weakness()
+                )?;
             }
-            if sum > target_sum {
+        }
+        Ok(())
+    }
+}
+
+fn find_invalid_numbers(numbers: &[u64], window_size: usize) -> Vec<(usize, u64)> {
+    let mut validator = XmasValidator::new(window_size);
+    numbers
+        .iter()
+        .enumerate()
+        .filter(|&(_, &n)| !validator.push(n))
+        .map(|(index, &n)| (index, n))
+        .collect()
+}
+
+// Every run of 2+ consecutive numbers adding up to target_sum, sorted by start index. Prefix sums of
+// unsigned numbers never decrease, so the runs ending at a given index are found with a binary search.
+fn find_contiguous_runs(numbers: &[u64], target_sum: u64) -> Vec<ContiguousRun> {
+    let mut prefix_sums = Vec::with_capacity(numbers.len() + 1);
+    prefix_sums.push(0u64);
+    for &n in numbers {
+        prefix_sums.push(prefix_sums.last().unwrap() + n);
+    }
+    let mut runs = Vec::new();
+    for end in 1..numbers.len() {
+        let start_sum = match prefix_sums[end + 1].checked_sub(target_sum) {
+            Some(start_sum) => start_sum,
+            None => continue,
+        };
+        let first_start = prefix_sums.partition_point(|&sum| sum < start_sum);
+        for start in first_start..end {
+            if prefix_sums[start] != start_sum {
                 break;
             }
+            let run = &numbers[start..=end];
+            runs.push(ContiguousRun {
+                start,
+                end,
+                min: *run.iter().min().unwrap(),
+                max: *run.iter().max().unwrap(),
+            });
         }
     }
-    String::from("No run found!")
+    runs.sort_unstable_by_key(|run| (run.start, run.end));
+    runs
+}
+
+fn anomaly_report(numbers: &[u64], window_size: usize) -> AnomalyReport {
+    AnomalyReport {
+        anomalies: find_invalid_numbers(numbers, window_size)
+            .into_iter()
+            .map(|(index, value)| Anomaly {
+                index,
+                value,
+                runs: find_contiguous_runs(numbers, value),
+            })
+            .collect(),
+    }
+}
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Input, history_size: usize, _: u64) -> Option<u64> {
+    find_invalid_numbers(&input.numbers, history_size)
+        .first()
+        .map(|&(_, n)| n)
+}
+
+fn solve_part2(input: &Input, _: usize, target_sum: u64) -> Option<u64> {
+    find_contiguous_runs(&input.numbers, target_sum)
+        .first()
+        .map(ContiguousRun::weakness)
 }
 
 // Day-specific code to process text data into custom problem state
@@ -69,8 +226,8 @@ fn process_text(
     processor: ProcessInputFunc,
     history_size: usize,
     target_sum: u64,
-    expected: &str,
-) -> String {
+    expected: Option<u64>,
+) -> Option<u64> {
     let state = parse_input_text(input_text);
     let actual = processor(&state, history_size, target_sum);
     assert_eq!(expected, actual);
@@ -82,13 +239,67 @@ fn process_file(
     processor: ProcessInputFunc,
     history_size: usize,
     target_sum: u64,
-    expected: &str,
-) -> String {
+    expected: Option<u64>,
+) -> Option<u64> {
     let contents =
         fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
     process_text(&contents, processor, history_size, target_sum, expected)
 }
 
+// Usage: day09 report [--window N] [FILE]
+fn run_report_tool(args: &[String]) {
+    let mut window_size = 25;
+    let mut filename = "inputs/input09.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--window" => {
+                window_size = args
+                    .next()
+                    .expect("--window needs a value")
+                    .parse()
+                    .unwrap()
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let input = parse_input_text(&contents);
+    print!("{}", anomaly_report(&input.numbers, window_size));
+}
+
+// Usage: day09 validate [--window N] [--target-sum N] < numbers.txt
+// Reports invalid numbers (and the contiguous run, if a target sum is given) as soon as they arrive.
+fn run_validate_tool(args: &[String]) {
+    let mut window_size = 25;
+    let mut finder = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .unwrap_or_else(|| panic!("{} needs a value", arg));
+        match arg.as_str() {
+            "--window" => window_size = value.parse().unwrap(),
+            "--target-sum" => finder = Some(ContiguousSumFinder::new(value.parse().unwrap())),
+            _ => panic!("Unknown option {}", arg),
+        }
+    }
+    let mut validator = XmasValidator::new(window_size);
+    let stdin = io::stdin();
+    for (index, line) in stdin.lock().lines().enumerate() {
+        let n = line.unwrap().trim().parse::<u64>().unwrap();
+        if !validator.push(n) {
+            println!("invalid: #{} = {}", index, n);
+        }
+        if let Some(run) = finder.as_mut().and_then(|f| f.push(n)) {
+            println!("run: #{}..#{} sums to the target", run.start, run.end - 1);
+            finder = None;
+        }
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 35
 20
@@ -113,21 +324,114 @@ const _TEST_INPUT1: &str = "\
 
 #[test]
 fn test_day09_part1() {
-    process_text(_TEST_INPUT1, solve_part1, 5, 0, "127");
+    process_text(_TEST_INPUT1, solve_part1, 5, 0, Some(127));
+    process_text("1\n2\n3\n5", solve_part1, 2, 0, None);
 }
 
 #[test]
 fn test_day09_part2() {
-    process_text(_TEST_INPUT1, solve_part2, 5, 127, "62");
+    process_text(_TEST_INPUT1, solve_part2, 5, 127, Some(62));
+    process_text(_TEST_INPUT1, solve_part2, 5, 1, None);
+}
+
+#[test]
+fn test_day09_anomaly_report() {
+    let input = parse_input_text(_TEST_INPUT1);
+    let report = anomaly_report(&input.numbers, 5);
+    assert_eq!(1, report.anomalies.len());
+    assert_eq!(
+        vec![ContiguousRun {
+            start: 2,
+            end: 5,
+            min: 15,
+            max: 47
+        }],
+        report.anomalies[0].runs
+    );
+    assert_eq!(
+        "#14: 127 is invalid\n    #2..=#5 adds up to it: min 15, max 47, min+max 62\n",
+        report.to_string()
+    );
+
+    // Several anomalies, runs padded with zeros, and an anomaly with no run at all
+    let numbers = [1, 2, 0, 4, 1, 0, 3, 40];
+    let report = anomaly_report(&numbers, 2);
+    let invalid: Vec<(usize, u64)> = report
+        .anomalies
+        .iter()
+        .map(|a| (a.index, a.value))
+        .collect();
+    assert_eq!(
+        vec![(2, 0), (3, 4), (4, 1), (5, 0), (6, 3), (7, 40)],
+        invalid
+    );
+    let runs: Vec<(usize, usize)> = report.anomalies[1]
+        .runs
+        .iter()
+        .map(|r| (r.start, r.end))
+        .collect();
+    assert_eq!(vec![(2, 3), (4, 6)], runs);
+    assert!(report.anomalies[5].runs.is_empty());
+    assert_eq!(
+        "All numbers are valid\n",
+        anomaly_report(&[1, 2, 3], 2).to_string()
+    );
+}
+
+#[test]
+fn test_day09_streaming() {
+    let input = parse_input_text(_TEST_INPUT1);
+    let mut validator = XmasValidator::new(5);
+    let invalid: Vec<u64> = input
+        .numbers
+        .iter()
+        .copied()
+        .filter(|&n| !validator.push(n))
+        .collect();
+    assert_eq!(vec![127], invalid);
+    // Only the sums of pairs still in the window are kept
+    let mut validator = XmasValidator::new(3);
+    for n in 1..1000 {
+        validator.push(n);
+    }
+    assert_eq!(3, validator.pair_sums.len());
+
+    let mut finder = ContiguousSumFinder::new(127);
+    let run = input.numbers.iter().find_map(|&n| finder.push(n));
+    assert_eq!(Some(2..6), run);
+    // a number equal to the target doesn't count as a run on its own
+    let mut finder = ContiguousSumFinder::new(5);
+    let runs: Vec<Option<Range<usize>>> = [9, 5, 7, 2, 3].iter().map(|&n| finder.push(n)).collect();
+    assert_eq!(vec![None, None, None, None, Some(3..5)], runs);
+}
+
+#[test]
+#[should_panic(expected = "at least one number")]
+fn test_day09_empty_window() {
+    XmasValidator::new(0);
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("report") => return run_report_tool(&args[1..]),
+        Some("validate") => return run_validate_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
-        process_file("inputs/input09.txt", solve_part1, 25, 0, "144381670")
+        process_file("inputs/input09.txt", solve_part1, 25, 0, Some(144381670)).unwrap()
     );
     println!(
         "Part 2: {}",
-        process_file("inputs/input09.txt", solve_part2, 25, 144381670, "20532569")
+        process_file(
+            "inputs/input09.txt",
+            solve_part2,
+            25,
+            144381670,
+            Some(20532569)
+        )
+        .unwrap()
     );
 }
@@ -1,57 +1,321 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::ops::AddAssign;
+
+// Which joltage differences an adapter accepts, and how far above the largest adapter the device is.
+#[derive(Clone, Debug)]
+struct Rules {
+    gaps: Vec<u32>, // sorted, no duplicates
+    device_offset: u32,
+}
+
+impl Rules {
+    fn new(gaps: &[u32], device_offset: u32) -> Rules {
+        let mut gaps = gaps.to_vec();
+        gaps.sort_unstable();
+        gaps.dedup();
+        Rules {
+            gaps,
+            device_offset,
+        }
+    }
+    fn max_gap(&self) -> u32 {
+        *self.gaps.last().unwrap_or(&0)
+    }
+    fn allows(&self, gap: u32) -> bool {
+        self.gaps.binary_search(&gap).is_ok()
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules::new(&[1, 2, 3], 3)
+    }
+}
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
 struct Input {
-    numbers: Vec<u32>,
+    numbers: Vec<u32>, // sorted; starts with the outlet (0) and ends with the device
+    rules: Rules,
+}
+
+// Unsigned integer that never overflows. Only addition is needed to count arrangements.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct BigUint {
+    limbs: Vec<u32>, // base 2^32, least significant first, no trailing zeros
+}
+
+impl BigUint {
+    fn from_u64(n: u64) -> BigUint {
+        let mut big = BigUint {
+            limbs: vec![n as u32, (n >> 32) as u32],
+        };
+        big.trim();
+        big
+    }
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+}
+
+impl AddAssign<&BigUint> for BigUint {
+    fn add_assign(&mut self, other: &BigUint) {
+        if self.limbs.len() < other.limbs.len() {
+            self.limbs.resize(other.limbs.len(), 0);
+        }
+        let mut carry = 0u64;
+        for (i, limb) in self.limbs.iter_mut().enumerate() {
+            let sum = *limb as u64 + *other.limbs.get(i).unwrap_or(&0) as u64 + carry;
+            *limb = sum as u32;
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peel off base-10^9 digits with long division
+        let mut limbs = self.limbs.clone();
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let mut remainder = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let value = (remainder << 32) | *limb as u64;
+                *limb = (value / 1_000_000_000) as u32;
+                remainder = value % 1_000_000_000;
+            }
+            chunks.push(remainder);
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+        match chunks.pop() {
+            None => write!(f, "0"),
+            Some(most_significant) => {
+                write!(f, "{}", most_significant)?;
+                for chunk in chunks.iter().rev() {
+                    write!(f, "{:09}", chunk)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 // Generic signature for "process problem state to get an answer"
 type ProcessInputFunc = fn(&Input) -> String;
 
-// concrete instance of a ProcessInputFunc implementation
-fn solve_part1(input: &Input) -> String {
-    let mut ones = 0;
-    let mut threes = 0;
-    for i in 1..input.numbers.len() {
-        match input.numbers[i] - input.numbers[i - 1] {
-            1 => {
-                ones += 1;
+// Indices of the adapters that can follow numbers[i] in a chain
+fn successors(input: &Input, i: usize) -> impl Iterator<Item = usize> + '_ {
+    let n = input.numbers[i];
+    (i + 1..input.numbers.len())
+        .take_while(move |&j| input.numbers[j] - n <= input.rules.max_gap())
+        .filter(move |&j| input.rules.allows(input.numbers[j] - n))
+}
+
+// Number of ways to get from each adapter to the device
+fn arrangement_counts(input: &Input) -> Vec<BigUint> {
+    let len = input.numbers.len();
+    let mut counts = vec![BigUint::default(); len];
+    counts[len - 1] = BigUint::from_u64(1);
+    for i in (0..len - 1).rev() {
+        let mut count = BigUint::default();
+        for j in successors(input, i) {
+            count += &counts[j];
+        }
+        counts[i] = count;
+    }
+    counts
+}
+
+// How many times each joltage difference occurs in the chain that uses every adapter, or the first
+// pair of adapters that are too far apart for such a chain to exist.
+fn gap_distribution(input: &Input) -> Result<BTreeMap<u32, usize>, (u32, u32)> {
+    let mut distribution = BTreeMap::new();
+    for pair in input.numbers.windows(2) {
+        let gap = pair[1] - pair[0];
+        if !input.rules.allows(gap) {
+            return Err((pair[0], pair[1]));
+        }
+        *distribution.entry(gap).or_default() += 1;
+    }
+    Ok(distribution)
+}
+
+// Lazily walks every valid chain from the outlet to the device, in lexicographic order. Adapters that
+// can't reach the device are skipped up front, so every step makes progress towards the next chain.
+struct Arrangements<'a> {
+    input: &'a Input,
+    reaches_device: Vec<bool>,
+    path: Vec<usize>, // indices into input.numbers
+    started: bool,
+}
+
+impl<'a> Arrangements<'a> {
+    fn new(input: &'a Input) -> Arrangements<'a> {
+        let reaches_device = arrangement_counts(input)
+            .iter()
+            .map(|count| !count.is_zero())
+            .collect();
+        Arrangements {
+            input,
+            reaches_device,
+            path: Vec::new(),
+            started: false,
+        }
+    }
+    fn next_step(&self, from: usize, after: usize) -> Option<usize> {
+        successors(self.input, from).find(|&j| j > after && self.reaches_device[j])
+    }
+    // Extends the path with the first available step until it reaches the device
+    fn descend(&mut self) {
+        let device = self.input.numbers.len() - 1;
+        while *self.path.last().unwrap() != device {
+            let from = *self.path.last().unwrap();
+            let next = self.next_step(from, from).unwrap();
+            self.path.push(next);
+        }
+    }
+}
+
+impl Iterator for Arrangements<'_> {
+    type Item = Vec<u32>;
+    fn next(&mut self) -> Option<Vec<u32>> {
+        if !self.started {
+            self.started = true;
+            if !self.reaches_device[0] {
+                return None;
             }
-            3 => {
-                threes += 1;
+            self.path.push(0);
+        } else {
+            // Backtrack to the deepest adapter with an untried alternative
+            loop {
+                let last = self.path.pop()?;
+                let from = *self.path.last()?;
+                if let Some(next) = self.next_step(from, last) {
+                    self.path.push(next);
+                    break;
+                }
             }
-            2 => {}
-            _ => panic!("unexpected sum"),
         }
+        self.descend();
+        Some(self.path.iter().map(|&i| self.input.numbers[i]).collect())
     }
-    (ones * threes).to_string()
 }
 
-fn solve_part2(input: &Input) -> String {
-    let mut counts = HashMap::<u32, u64>::new();
-    counts.insert(*input.numbers.last().unwrap(), 1u64);
-    for i in (0..input.numbers.len() - 1).rev() {
-        let mut count = 0u64;
-        let n = input.numbers[i];
-        for d in 1..4 {
-            count += counts.get(&(n + d)).unwrap_or(&0u64);
+struct KitAnalysis {
+    mandatory: Vec<u32>,      // adapters on every valid chain
+    removable: Vec<u32>,      // everything else: at least one valid chain doesn't need them
+    shortest_chain: Vec<u32>, // outlet, fewest possible adapters, device
+}
+
+// Which adapters a kit can do without, or None if there's no valid chain at all. Indices are already
+// in topological order, so an adapter is on every chain exactly when it's on some chain and no usable
+// connection jumps over it.
+fn analyze_kit(input: &Input) -> Option<KitAnalysis> {
+    let len = input.numbers.len();
+    let device = len - 1;
+    let mut reaches_device = vec![false; len];
+    reaches_device[device] = true;
+    for i in (0..device).rev() {
+        reaches_device[i] = successors(input, i).any(|j| reaches_device[j]);
+    }
+    if !reaches_device[0] {
+        return None;
+    }
+    let mut from_outlet = vec![false; len];
+    from_outlet[0] = true;
+    // bypass_depth[k] counts usable connections a->b with a < k < b (as a running sum of +1/-1 marks)
+    let mut bypass_marks = vec![0i32; len + 1];
+    for i in 0..device {
+        if !from_outlet[i] {
+            continue;
+        }
+        for j in successors(input, i).filter(|&j| reaches_device[j]) {
+            from_outlet[j] = true;
+            bypass_marks[i + 1] += 1;
+            bypass_marks[j] -= 1;
+        }
+    }
+    let mut mandatory = Vec::new();
+    let mut removable = Vec::new();
+    let mut bypass_depth = 0;
+    for i in 1..device {
+        bypass_depth += bypass_marks[i];
+        if from_outlet[i] && reaches_device[i] && bypass_depth == 0 {
+            mandatory.push(input.numbers[i]);
+        } else {
+            removable.push(input.numbers[i]);
+        }
+    }
+
+    // Fewest hops to the device from each adapter; ties go to the lowest next adapter
+    let mut hops = vec![usize::MAX; len];
+    let mut next_hop = vec![0; len];
+    hops[device] = 0;
+    for i in (0..device).rev() {
+        if let Some(j) = successors(input, i)
+            .filter(|&j| hops[j] != usize::MAX)
+            .min_by_key(|&j| hops[j])
+        {
+            hops[i] = hops[j] + 1;
+            next_hop[i] = j;
         }
-        counts.insert(n, count);
     }
-    counts.get(&0).unwrap().to_string()
+    let mut shortest_chain = vec![input.numbers[0]];
+    let mut i = 0;
+    while i != device {
+        i = next_hop[i];
+        shortest_chain.push(input.numbers[i]);
+    }
+
+    Some(KitAnalysis {
+        mandatory,
+        removable,
+        shortest_chain,
+    })
+}
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Input) -> String {
+    match gap_distribution(input) {
+        Ok(distribution) => {
+            let ones = distribution.get(&1).unwrap_or(&0);
+            let threes = distribution.get(&3).unwrap_or(&0);
+            (ones * threes).to_string()
+        }
+        Err((a, b)) => format!("No chain uses every adapter: {} can't connect to {}", a, b),
+    }
+}
+
+fn solve_part2(input: &Input) -> String {
+    arrangement_counts(input)[0].to_string()
 }
 
 // Day-specific code to process text data into custom problem state
-fn parse_input_text(input_text: &str) -> Input {
+fn parse_input_text_with_rules(input_text: &str, rules: Rules) -> Input {
     let mut numbers: Vec<u32> = input_text
         .lines()
         .map(|line| line.parse::<u32>().unwrap())
         .collect();
     numbers.push(0);
     numbers.sort_unstable();
-    numbers.push(numbers.last().unwrap() + 3);
-    Input { numbers }
+    numbers.push(numbers.last().unwrap() + rules.device_offset);
+    Input { numbers, rules }
+}
+
+fn parse_input_text(input_text: &str) -> Input {
+    parse_input_text_with_rules(input_text, Rules::default())
 }
 
 fn process_text(input_text: &str, processor: ProcessInputFunc, expected: &str) -> String {
@@ -67,6 +331,68 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Parses [--gaps 1,2,3] [--device-offset 3] [--list N] [FILE]
+fn load_input_from_args(args: &[String]) -> (Input, usize) {
+    let mut rules = Rules::default();
+    let mut list_count = 0;
+    let mut filename = "inputs/input10.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--gaps" => {
+                let gaps: Vec<u32> = value().split(',').map(|g| g.parse().unwrap()).collect();
+                rules = Rules::new(&gaps, rules.device_offset);
+            }
+            "--device-offset" => rules.device_offset = value().parse().unwrap(),
+            "--list" => list_count = value().parse().unwrap(),
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    (parse_input_text_with_rules(&contents, rules), list_count)
+}
+
+fn format_joltages(joltages: &[u32], separator: &str) -> String {
+    let joltages: Vec<String> = joltages.iter().map(|n| n.to_string()).collect();
+    joltages.join(separator)
+}
+
+// Usage: day10 arrangements [--gaps 1,2,3] [--device-offset 3] [--list N] [FILE]
+fn run_arrangements_tool(args: &[String]) {
+    let (input, list_count) = load_input_from_args(args);
+    match gap_distribution(&input) {
+        Ok(distribution) => println!("Gaps using every adapter: {:?}", distribution),
+        Err((a, b)) => println!("No chain uses every adapter: {} can't connect to {}", a, b),
+    }
+    println!("Arrangements: {}", arrangement_counts(&input)[0]);
+    for chain in Arrangements::new(&input).take(list_count) {
+        println!("{}", format_joltages(&chain, " -> "));
+    }
+}
+
+// Usage: day10 kit [--gaps 1,2,3] [--device-offset 3] [FILE]
+fn run_kit_tool(args: &[String]) {
+    let (input, _) = load_input_from_args(args);
+    match analyze_kit(&input) {
+        Some(kit) => {
+            println!("Mandatory: {}", format_joltages(&kit.mandatory, ", "));
+            println!("Removable: {}", format_joltages(&kit.removable, ", "));
+            println!(
+                "Shortest chain ({} adapters): {}",
+                kit.shortest_chain.len() - 2,
+                format_joltages(&kit.shortest_chain, " -> ")
+            );
+        }
+        None => println!("No valid chain from the outlet to the device"),
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 16
 10
@@ -125,7 +451,65 @@ fn test_day10_part2() {
     process_text(_TEST_INPUT2, solve_part2, "19208");
 }
 
+#[test]
+fn test_day10_rules_and_arrangements() {
+    let input = parse_input_text(_TEST_INPUT1);
+    let chains: Vec<Vec<u32>> = Arrangements::new(&input).collect();
+    assert_eq!(8, chains.len());
+    assert_eq!(
+        vec![0, 1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19, 22],
+        chains[0]
+    );
+    assert_eq!(vec![0, 1, 4, 7, 10, 12, 15, 16, 19, 22], chains[7]);
+
+    // Only gaps of 1 and 3, with the device 5 jolts up: it can't be reached at all
+    let input = parse_input_text_with_rules(_TEST_INPUT1, Rules::new(&[3, 1], 5));
+    assert_eq!(24, *input.numbers.last().unwrap());
+    assert_eq!(Err((19, 24)), gap_distribution(&input));
+    assert_eq!("0", solve_part2(&input));
+    let input = parse_input_text_with_rules(_TEST_INPUT1, Rules::new(&[1, 3], 3));
+    assert_eq!("2", solve_part2(&input));
+    assert_eq!(2, Arrangements::new(&input).count());
+    let input = parse_input_text_with_rules(_TEST_INPUT1, Rules::new(&[1, 3, 5], 5));
+    assert_eq!("28", solve_part2(&input));
+    assert_eq!(28, Arrangements::new(&input).count());
+    let input = parse_input_text_with_rules("1\n9", Rules::default());
+    assert_eq!("0", solve_part2(&input));
+    assert_eq!(0, Arrangements::new(&input).count());
+
+    // 300 adapters in a row: tribonacci-style growth overflows u64 long before the end
+    let adapters: Vec<String> = (1..=300).map(|n| n.to_string()).collect();
+    let input = parse_input_text(&adapters.join("\n"));
+    assert_eq!(
+        "15350287614359738671843506567023635268924281173051801861566524609184461020990367",
+        solve_part2(&input)
+    );
+}
+
+#[test]
+fn test_day10_kit_analysis() {
+    let input = parse_input_text(_TEST_INPUT1);
+    let kit = analyze_kit(&input).unwrap();
+    assert_eq!(vec![1, 4, 7, 10, 12, 15, 16, 19], kit.mandatory);
+    assert_eq!(vec![5, 6, 11], kit.removable);
+    assert_eq!(vec![0, 1, 4, 7, 10, 12, 15, 16, 19, 22], kit.shortest_chain);
+    // Adapters 1 and 4 aren't on any chain at all
+    let input = parse_input_text_with_rules("1\n2\n4\n5", Rules::new(&[2, 3], 3));
+    let kit = analyze_kit(&input).unwrap();
+    assert_eq!(vec![2, 5], kit.mandatory);
+    assert_eq!(vec![1, 4], kit.removable);
+    assert_eq!(vec![0, 2, 5, 8], kit.shortest_chain);
+    assert!(analyze_kit(&parse_input_text("1\n9")).is_none());
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("kit") => return run_kit_tool(&args[1..]),
+        Some("arrangements") => return run_arrangements_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input10.txt", solve_part1, "2263")
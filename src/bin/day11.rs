@@ -1,203 +1,699 @@
-use std::convert::TryFrom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+    Floor,
+    Empty,
+    Occupied,
+}
+
+// Which characters represent each kind of cell in the text form of a seat map
+#[derive(Clone, Copy, Debug)]
+struct Alphabet {
+    floor: u8,
+    empty: u8,
+    occupied: u8,
+}
+
+impl Alphabet {
+    fn decode(&self, c: u8) -> Option<Cell> {
+        match c {
+            _ if c == self.floor => Some(Cell::Floor),
+            _ if c == self.empty => Some(Cell::Empty),
+            _ if c == self.occupied => Some(Cell::Occupied),
+            _ => None,
+        }
+    }
+    fn encode(&self, cell: Cell) -> u8 {
+        match cell {
+            Cell::Floor => self.floor,
+            Cell::Empty => self.empty,
+            Cell::Occupied => self.occupied,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Neighborhood {
+    Adjacent,    // the 8 surrounding cells
+    LineOfSight, // the first seat in each of the 8 directions, looking across floor
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+    neighborhood: Neighborhood,
+    occupy_threshold: u32, // an empty seat fills up if at most this many neighbors are occupied
+    vacate_threshold: u32, // an occupied seat empties if at least this many neighbors are occupied
+    alphabet: Alphabet,
+}
+
+const PUZZLE_ALPHABET: Alphabet = Alphabet {
+    floor: b'.',
+    empty: b'L',
+    occupied: b'#',
+};
+const PART1_RULES: Rules = Rules {
+    neighborhood: Neighborhood::Adjacent,
+    occupy_threshold: 0,
+    vacate_threshold: 4,
+    alphabet: PUZZLE_ALPHABET,
+};
+const PART2_RULES: Rules = Rules {
+    neighborhood: Neighborhood::LineOfSight,
+    occupy_threshold: 0,
+    vacate_threshold: 5,
+    alphabet: PUZZLE_ALPHABET,
+};
+
+const DIRECTIONS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
 struct Input {
     width: i32,
     height: i32,
-    grid_old: Vec<u8>,
-    grid_new: Vec<u8>,
+    grid: Vec<u8>, // raw characters; decoded by whichever Rules are used to simulate it
 }
 
-impl Input {
-    fn is_valid(self: &Input, x: i32, y: i32) -> bool {
-        x >= 0 && x < self.width && y >= 0 && y < self.height
+// What a cell becomes in the next generation, given how many of its neighbors are occupied
+fn next_state(rules: &Rules, cell: Cell, occupied_neighbors: u32) -> Cell {
+    match cell {
+        Cell::Empty if occupied_neighbors <= rules.occupy_threshold => Cell::Occupied,
+        Cell::Occupied if occupied_neighbors >= rules.vacate_threshold => Cell::Empty,
+        cell => cell,
     }
-    fn get_old(self: &Input, x: i32, y: i32) -> Option<u8> {
-        match self.is_valid(x, y) {
-            true => Some(self.grid_old[usize::try_from(y * self.width + x).unwrap()]),
-            false => None,
+}
+
+// Word w of the bitset shifted so that bit i holds bit i + offset; bits past either end read as 0
+fn shifted_word(src: &[u64], w: usize, offset: isize) -> u64 {
+    let j = w as isize + offset.div_euclid(64);
+    let bit = offset.rem_euclid(64) as u32;
+    let word = |j: isize| {
+        if j >= 0 && j < src.len() as isize {
+            src[j as usize]
+        } else {
+            0
         }
+    };
+    (word(j) >> bit) | (word(j + 1) << (63 - bit) << 1)
+}
+
+// dst bit i = src bit i + offset, for every bit; bits past either end of src read as 0
+fn shift_into(dst: &mut [u64], src: &[u64], offset: isize) {
+    let word_offset = offset.div_euclid(64);
+    let bit = offset.rem_euclid(64) as u32;
+    let combine = |low: u64, high: u64| (low >> bit) | (high << (63 - bit) << 1);
+    // Only the words near the ends need to check for reading past src
+    let n = dst.len() as isize;
+    let start = (-word_offset).clamp(0, n);
+    let end = (src.len() as isize - 1 - word_offset).clamp(start, n);
+    for w in (0..start).chain(end..n) {
+        dst[w as usize] = shifted_word(src, w as usize, offset);
     }
-    fn set_new(self: &mut Input, x: i32, y: i32, val: u8) {
-        match self.is_valid(x, y) {
-            true => self.grid_new[usize::try_from(y * self.width + x).unwrap()] = val,
-            false => {}
+    if end > start {
+        let pairs =
+            src[(start + word_offset) as usize..(end + word_offset) as usize + 1].windows(2);
+        for (out, pair) in dst[start as usize..end as usize].iter_mut().zip(pairs) {
+            *out = combine(pair[0], pair[1]);
         }
     }
-    fn swap(self: &mut Input) {
-        std::mem::swap(&mut self.grid_old, &mut self.grid_new);
-    }
-    fn is_stable(self: &Input) -> bool {
-        for i in 0..self.grid_new.len() {
-            if self.grid_new[i] != self.grid_old[i] {
-                return false;
+}
+
+// Iterates over the indices of the set bits, in ascending order
+fn set_bits(bits: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    bits.iter().enumerate().flat_map(|(w, &word)| {
+        std::iter::successors(Some(word), |&rest| Some(rest & rest.wrapping_sub(1)))
+            .take_while(|&rest| rest != 0)
+            .map(move |rest| w * 64 + rest.trailing_zeros() as usize)
+    })
+}
+
+fn decode_grid(input: &Input, rules: &Rules) -> Vec<Cell> {
+    input
+        .grid
+        .iter()
+        .map(|&c| {
+            rules
+                .alphabet
+                .decode(c)
+                .unwrap_or_else(|| panic!("Unexpected char {}", c as char))
+        })
+        .collect()
+}
+
+// Everything needed to find, for all cells at once, whether the seat they see in one direction is
+// occupied. Looking across floor is done by doubling: first the adjacent cell, then up to 2 cells,
+// 4 cells and so on, each level only reaching further from cells that see nothing but floor so far.
+// Long floor runs are rare, so each level only keeps its nonzero words.
+#[derive(Clone)]
+struct Sightline {
+    offset: isize,     // index distance to the adjacent cell in this direction
+    on_grid: Vec<u64>, // cells whose adjacent cell in this direction is on the grid
+    floor_runs: Vec<Vec<(usize, u64)>>, // level k: cells whose next 2^k cells this way are floor
+}
+
+// Seat map simulation. What every seat can see never changes, so each seat's visible neighbors
+// are listed once up front; the relation is symmetric, for both neighborhoods. A seat can only
+// change if it or one of its neighbors changed in the previous generation, so when few seats
+// changed, only those seats and their neighbors (the frontier) are re-evaluated.
+// On large maps most seats often keep flipping for hundreds of generations, and then the frontier
+// is most of the map. Those generations evaluate every cell instead, 64 at a time: the seats and
+// occupied seats are bitsets, and every cell's occupied neighbors are counted in bit-sliced form
+// (one bitset per bit of the count), built up one direction at a time from shifted copies of the
+// occupied bitset.
+#[derive(Clone)]
+struct Automaton {
+    rules: Rules,
+    width: i32,
+    height: i32,
+    len: usize,
+    seats: Vec<u64>,
+    occupied: Vec<u64>,
+    // The neighbors of cell i are neighbor_list[neighbor_start[i]..neighbor_start[i + 1]]
+    neighbor_start: Vec<u32>,
+    neighbor_list: Vec<u32>,
+    sightlines: Vec<Sightline>,
+    fills: [u64; 9], // all ones at n if an empty seat with n occupied neighbors fills up, else 0
+    empties: [u64; 9], // all ones at n if an occupied seat with n occupied neighbors empties
+    changed: Vec<u64>, // cells that changed in the last step
+    last_changes: Option<usize>, // how many; None before the first step, when every cell is new
+    frontier: Vec<u64>, // scratch space for marking the frontier; all zero between steps
+    generation: usize,
+}
+
+// Generations where fewer than one cell in this many changed re-evaluate just the frontier
+const FRONTIER_SPARSITY: usize = 64;
+
+impl Automaton {
+    fn new(input: &Input, rules: Rules) -> Automaton {
+        let cells = decode_grid(input, &rules);
+        let words = cells.len() / 64 + 1;
+        let bitset = |test: &dyn Fn(usize) -> bool| {
+            let mut bits = vec![0u64; words];
+            for i in (0..cells.len()).filter(|&i| test(i)) {
+                bits[i / 64] |= 1 << (i % 64);
             }
-        }
-        true
-    }
-    fn num_occupied_neighbors(self: &Input, x: i32, y: i32) -> u32 {
-        let mut count = 0;
-        for ny in y - 1..y + 2 {
-            for nx in x - 1..x + 2 {
-                if x != nx || y != ny {
-                    if let Some(b'#') = self.get_old(nx, ny) {
-                        count += 1;
+            bits
+        };
+        let floor = bitset(&|i| cells[i] == Cell::Floor);
+        let (width, height) = (input.width, input.height);
+        let sightlines = DIRECTIONS
+            .iter()
+            .map(|&(dx, dy)| {
+                let offset = (dy * width + dx) as isize;
+                let on_grid = bitset(&|i| {
+                    let (x, y) = (i as i32 % width + dx, i as i32 / width + dy);
+                    x >= 0 && x < width && y >= 0 && y < height
+                });
+                let mut floor_runs = Vec::new();
+                if rules.neighborhood == Neighborhood::LineOfSight {
+                    let mut run = vec![0; words];
+                    shift_into(&mut run, &floor, offset);
+                    for (r, g) in run.iter_mut().zip(on_grid.iter()) {
+                        *r &= g;
+                    }
+                    while run.iter().any(|&r| r != 0) {
+                        let mut further = vec![0; words];
+                        shift_into(&mut further, &run, offset << floor_runs.len());
+                        for (f, r) in further.iter_mut().zip(run.iter()) {
+                            *f &= r;
+                        }
+                        let nonzero = run.iter().enumerate().filter(|&(_, &r)| r != 0);
+                        floor_runs.push(nonzero.map(|(w, &r)| (w, r)).collect());
+                        run = further;
+                    }
+                }
+                Sightline {
+                    offset,
+                    on_grid,
+                    floor_runs,
+                }
+            })
+            .collect();
+        // The seat that counts as cell i's neighbor in direction (dx,dy), if any
+        let neighbor = |i: usize, (dx, dy): (i32, i32)| {
+            let (mut x, mut y) = (i as i32 % width + dx, i as i32 / width + dy);
+            while x >= 0 && x < width && y >= 0 && y < height {
+                match cells[(y * width + x) as usize] {
+                    Cell::Floor if rules.neighborhood == Neighborhood::LineOfSight => {
+                        x += dx;
+                        y += dy;
                     }
+                    Cell::Floor => return None,
+                    _ => return Some((y * width + x) as u32),
                 }
             }
-        }
-        count
-    }
-    #[rustfmt::skip]
-    fn num_occupied_visible_neighbors(self:&Input, x:i32, y:i32) -> u32 {
-        let mut count = 0;
-        // NW
-        let mut nx = x-1;
-        let mut ny = y-1;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { nx -= 1; ny -= 1; }
-                _ => {panic!("Unexpected char");}
+            None
+        };
+        let mut neighbor_start = Vec::with_capacity(cells.len() + 1);
+        let mut neighbor_list = Vec::new();
+        for (i, &cell) in cells.iter().enumerate() {
+            neighbor_start.push(neighbor_list.len() as u32);
+            if cell != Cell::Floor {
+                neighbor_list.extend(DIRECTIONS.iter().filter_map(|&d| neighbor(i, d)));
             }
         }
-        // N
-        let nx = x;
-        let mut ny = y-1;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { ny -= 1; }
-                _ => {panic!("Unexpected char");}
+        neighbor_start.push(neighbor_list.len() as u32);
+        let flips = |cell: Cell| {
+            let mut select = [0; 9];
+            for (n, s) in select.iter_mut().enumerate() {
+                if next_state(&rules, cell, n as u32) != cell {
+                    *s = u64::MAX;
+                }
             }
+            select
+        };
+        Automaton {
+            rules,
+            width,
+            height,
+            len: cells.len(),
+            seats: bitset(&|i| cells[i] != Cell::Floor),
+            occupied: bitset(&|i| cells[i] == Cell::Occupied),
+            neighbor_start,
+            neighbor_list,
+            sightlines,
+            fills: flips(Cell::Empty),
+            empties: flips(Cell::Occupied),
+            changed: vec![0; words],
+            last_changes: None,
+            frontier: vec![0; words],
+            generation: 0,
         }
-        // NE
-        let mut nx = x+1;
-        let mut ny = y-1;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { nx += 1; ny -= 1; }
-                _ => {panic!("Unexpected char");}
-            }
+    }
+    fn cell(&self, i: usize) -> Cell {
+        let bit = 1 << (i % 64);
+        if self.occupied[i / 64] & bit != 0 {
+            Cell::Occupied
+        } else if self.seats[i / 64] & bit != 0 {
+            Cell::Empty
+        } else {
+            Cell::Floor
         }
-        // W
-        let mut nx = x-1;
-        let ny = y;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { nx -= 1; }
-                _ => {panic!("Unexpected char");}
+    }
+    fn cells(&self) -> Vec<Cell> {
+        (0..self.len).map(|i| self.cell(i)).collect()
+    }
+    // Cells that changed in the last step
+    fn changed(&self) -> impl Iterator<Item = usize> + '_ {
+        set_bits(&self.changed)
+    }
+    fn neighbors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let range = self.neighbor_start[i] as usize..self.neighbor_start[i + 1] as usize;
+        self.neighbor_list[range].iter().map(|&n| n as usize)
+    }
+    // Advances one generation. Returns how many cells changed.
+    fn step(&mut self) -> usize {
+        let changes = match self.last_changes {
+            Some(changes) if changes * FRONTIER_SPARSITY < self.len => self.step_frontier(),
+            _ => self.step_all(),
+        };
+        self.last_changes = Some(changes);
+        self.generation += 1;
+        changes
+    }
+    // Re-evaluates only the seats that changed in the last step, and the seats that can see them
+    fn step_frontier(&mut self) -> usize {
+        let mut marked = std::mem::take(&mut self.frontier);
+        let mut frontier = Vec::new();
+        for i in self.changed() {
+            for j in std::iter::once(i).chain(self.neighbors(i)) {
+                let bit = 1 << (j % 64);
+                if marked[j / 64] & bit == 0 {
+                    marked[j / 64] |= bit;
+                    frontier.push(j);
+                }
             }
         }
-        // E
-        let mut nx = x+1;
-        let ny = y;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { nx += 1; }
-                _ => {panic!("Unexpected char");}
-            }
+        // Decide every change before applying any of them
+        let flips: Vec<usize> = frontier
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let occupied = self
+                    .neighbors(i)
+                    .filter(|&n| self.occupied[n / 64] >> (n % 64) & 1 == 1)
+                    .count() as u32;
+                let cell = self.cell(i);
+                next_state(&self.rules, cell, occupied) != cell
+            })
+            .collect();
+        // Every cell that changed last time is in the frontier, so this clears them all
+        for &i in frontier.iter() {
+            marked[i / 64] = 0;
+            self.changed[i / 64] = 0;
         }
-        // SW
-        let mut nx = x-1;
-        let mut ny = y+1;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { nx -= 1; ny += 1; }
-                _ => {panic!("Unexpected char");}
-            }
+        self.frontier = marked;
+        for &i in flips.iter() {
+            self.occupied[i / 64] ^= 1 << (i % 64);
+            self.changed[i / 64] |= 1 << (i % 64);
         }
-        // S
-        let nx = x;
-        let mut ny = y+1;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { ny += 1; }
-                _ => {panic!("Unexpected char");}
+        flips.len()
+    }
+    // Re-evaluates every cell, 64 at a time
+    fn step_all(&mut self) -> usize {
+        let words = self.occupied.len();
+        // count[w][b] holds bit b of the occupied neighbor count of each cell in word w
+        let mut count = vec![[0u64; 4]; words];
+        let mut seen = vec![0; words];
+        for line in self.sightlines.iter() {
+            shift_into(&mut seen, &self.occupied, line.offset);
+            for (s, g) in seen.iter_mut().zip(line.on_grid.iter()) {
+                *s &= g;
+            }
+            // Updating in place can only pick up seats from further along the same floor run,
+            // which are just as valid
+            for (level, floor_run) in line.floor_runs.iter().enumerate() {
+                for &(w, r) in floor_run.iter() {
+                    seen[w] |= shifted_word(&seen, w, line.offset << level) & r;
+                }
+            }
+            for (c, &s) in count.iter_mut().zip(seen.iter()) {
+                let mut carry = s;
+                for bit in c.iter_mut() {
+                    let sum = *bit ^ carry;
+                    carry &= *bit;
+                    *bit = sum;
+                }
             }
         }
-        // SE
-        let mut nx = x+1;
-        let mut ny = y+1;
-        loop {
-            match self.get_old(nx,ny) {
-                Some(b'#') => { count += 1; break; }
-                Some(b'L') | None => { break ;}
-                Some(b'.') => { nx += 1; ny += 1; }
-                _ => {panic!("Unexpected char");}
+        // Decide every change from the counts before applying any of them
+        let mut changes = 0;
+        for (w, c) in count.iter().enumerate() {
+            let occupied = self.occupied[w];
+            let empty = self.seats[w] & !occupied;
+            // Cells with a count of n are low[n % 4] & high[n / 4]
+            let low = [!c[0] & !c[1], c[0] & !c[1], !c[0] & c[1], c[0] & c[1]];
+            let high = [!c[2] & !c[3], c[2] & !c[3], !c[2] & c[3]];
+            let (mut fill, mut vacate) = (0, 0);
+            for n in 0..9 {
+                let has_n = low[n % 4] & high[n / 4];
+                fill |= has_n & self.fills[n];
+                vacate |= has_n & self.empties[n];
             }
+            let flips = (fill & empty) | (vacate & occupied);
+            self.occupied[w] ^= flips;
+            self.changed[w] = flips;
+            changes += flips.count_ones() as usize;
         }
-        count
-    }
-    fn num_occupied(self: &Input) -> u32 {
-        // TODO(cort): functional nonsense?
-        let mut count = 0;
-        for &b in self.grid_old.iter() {
-            if b == b'#' {
-                count += 1;
+        changes
+    }
+    // Runs until the seating settles or starts repeating, giving up after max_generations.
+    // States are identified by a Zobrist hash (a random key per seat, xored together for the occupied
+    // ones), which is updated from each step's changes; a repeat is confirmed by simulating one more
+    // period on a copy before it is reported.
+    fn run(&mut self, max_generations: usize) -> Convergence {
+        self.run_observed(max_generations, |_| {})
+    }
+    // Same as run, calling observe with the starting state and again after every generation that
+    // changes something
+    fn run_observed(
+        &mut self,
+        max_generations: usize,
+        mut observe: impl FnMut(&Automaton),
+    ) -> Convergence {
+        observe(self);
+        let mut rng = StdRng::seed_from_u64(11);
+        let keys: Vec<u64> = (0..self.len).map(|_| rng.gen()).collect();
+        let mut hash = set_bits(&self.occupied).fold(0, |hash, i| hash ^ keys[i]);
+        let mut seen = HashMap::new();
+        seen.insert(hash, self.generation);
+        while self.generation < max_generations {
+            if self.step() == 0 {
+                return Convergence::Stable {
+                    generation: self.generation - 1,
+                    occupied: self.count(Cell::Occupied),
+                };
+            }
+            observe(self);
+            hash = self.changed().fold(hash, |hash, i| hash ^ keys[i]);
+            if let Some(&start) = seen.get(&hash) {
+                let period = self.generation - start;
+                let mut probe = self.clone();
+                for _ in 0..period {
+                    probe.step();
+                }
+                if probe.occupied == self.occupied {
+                    return Convergence::Cycle { start, period };
+                }
             }
+            seen.insert(hash, self.generation);
+        }
+        Convergence::DidNotConverge {
+            generations: self.generation,
         }
-        count
+    }
+    fn run_for(&mut self, max_generations: usize) {
+        while self.generation < max_generations && self.step() > 0 {}
+    }
+    fn count(&self, cell: Cell) -> usize {
+        let ones = |bits: &[u64]| bits.iter().map(|w| w.count_ones() as usize).sum::<usize>();
+        match cell {
+            Cell::Floor => self.len - ones(&self.seats),
+            Cell::Empty => ones(&self.seats) - ones(&self.occupied),
+            Cell::Occupied => ones(&self.occupied),
+        }
+    }
+    fn render(&self) -> String {
+        let rows: Vec<String> = self
+            .cells()
+            .chunks(self.width as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|&c| self.rules.alphabet.encode(c) as char)
+                    .collect()
+            })
+            .collect();
+        rows.join("\n")
+    }
+    fn render_ppm(&self, scale: usize) -> Vec<u8> {
+        ppm(self.width, self.height, scale, |i| match self.cell(i) {
+            Cell::Floor => FLOOR_COLOR,
+            Cell::Empty => [60, 160, 60],
+            Cell::Occupied => [200, 40, 40],
+        })
     }
 }
 
-// Generic signature for "process problem state to get an answer"
-type ProcessInputFunc = fn(&mut Input) -> String;
+const FLOOR_COLOR: [u8; 3] = [30, 30, 50];
 
-// concrete instance of a ProcessInputFunc implementation
-#[rustfmt::skip]
-fn solve_part1(input: &mut Input) -> String {
-    loop {
-        for y in 0..input.height {
-            for x in 0..input.width {
-                input.set_new(x, y, match input.get_old(x,y) {
-                    Some(b'L') => if input.num_occupied_neighbors(x,y) == 0 { b'#' } else { b'L' },
-                    Some(b'#') => if input.num_occupied_neighbors(x,y) >= 4 { b'L' } else { b'#' },
-                    Some(b'.') => b'.',
-                    _ => b'?',
-                });
-            }
+// Binary PPM image, each cell drawn as a scale x scale square of the color pixel(index) gives it
+fn ppm(width: i32, height: i32, scale: usize, pixel: impl Fn(usize) -> [u8; 3]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut image = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            image.extend_from_slice(&pixel(y / scale * width + x / scale));
         }
-        if input.is_stable() {
-            break;
+    }
+    image
+}
+
+// Black through red and yellow to white, for t from 0 to 1
+fn heat_color(t: f64) -> [u8; 3] {
+    let channel = |start: f64| (((t * 3.0 - start).clamp(0.0, 1.0)) * 255.0) as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+// How often, and how recently, every seat changed over a run
+struct Heatmap {
+    width: i32,
+    height: i32,
+    seats: Vec<bool>,
+    changes: Vec<u32>,
+    last_changed: Vec<Option<usize>>,
+}
+
+impl Heatmap {
+    fn new(automaton: &Automaton) -> Heatmap {
+        let len = automaton.len;
+        Heatmap {
+            width: automaton.width,
+            height: automaton.height,
+            seats: (0..len).map(|i| automaton.cell(i) != Cell::Floor).collect(),
+            changes: vec![0; len],
+            last_changed: vec![None; len],
         }
-        input.swap();
     }
-    input.num_occupied().to_string()
+    fn record(&mut self, automaton: &Automaton) {
+        for i in automaton.changed() {
+            self.changes[i] += 1;
+            self.last_changed[i] = Some(automaton.generation);
+        }
+    }
+    fn changes_ppm(&self, scale: usize) -> Vec<u8> {
+        let most = self.changes.iter().copied().max().unwrap_or(0).max(1);
+        ppm(self.width, self.height, scale, |i| match self.seats[i] {
+            false => FLOOR_COLOR,
+            true => heat_color(self.changes[i] as f64 / most as f64),
+        })
+    }
+    fn last_changed_ppm(&self, scale: usize) -> Vec<u8> {
+        let latest = self
+            .last_changed
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        ppm(self.width, self.height, scale, |i| match self.seats[i] {
+            false => FLOOR_COLOR,
+            true => heat_color(self.last_changed[i].unwrap_or(0) as f64 / latest as f64),
+        })
+    }
+    // One row of right-aligned numbers per grid row, with '.' for floor and '-' for seats that never
+    // changed
+    fn render_numbers(&self, values: impl Fn(usize) -> Option<usize>) -> String {
+        let cells: Vec<String> = (0..self.seats.len())
+            .map(|i| match (self.seats[i], values(i)) {
+                (false, _) => ".".to_string(),
+                (true, None) => "-".to_string(),
+                (true, Some(value)) => value.to_string(),
+            })
+            .collect();
+        let column_width = cells.iter().map(String::len).max().unwrap_or(1);
+        let rows: Vec<String> = cells
+            .chunks(self.width as usize)
+            .map(|row| {
+                let row: Vec<String> = row
+                    .iter()
+                    .map(|cell| format!("{:>1$}", cell, column_width))
+                    .collect();
+                row.join(" ")
+            })
+            .collect();
+        rows.join("\n")
+    }
+    fn render_changes(&self) -> String {
+        self.render_numbers(|i| Some(self.changes[i] as usize).filter(|&n| n > 0))
+    }
+    fn render_last_changed(&self) -> String {
+        self.render_numbers(|i| self.last_changed[i])
+    }
 }
 
-#[rustfmt::skip]
-fn solve_part2(input: &mut Input) -> String {
-    loop {
-        for y in 0..input.height {
-            for x in 0..input.width {
-                input.set_new(x, y, match input.get_old(x,y) {
-                    Some(b'L') => if input.num_occupied_visible_neighbors(x,y) == 0 { b'#' } else { b'L' },
-                    Some(b'#') => if input.num_occupied_visible_neighbors(x,y) >= 5 { b'L' } else { b'#' },
-                    Some(b'.') => b'.',
-                    _ => b'?',
-                });
+// The straightforward version: rescan every cell and walk every line of sight, every generation.
+// Kept as a reference for tests and the benchmark. Stops when stable or after max_generations
+// (some maps never settle), returning the final cells and generation count.
+fn rescan(input: &Input, rules: Rules, max_generations: usize) -> (Vec<Cell>, usize) {
+    let (width, height) = (input.width, input.height);
+    let mut cells = decode_grid(input, &rules);
+    let mut generation = 0;
+    while generation < max_generations {
+        // Whether the first seat seen from (x,y) in direction (dx,dy) is occupied
+        let sees_occupied = |x: i32, y: i32, dx: i32, dy: i32| {
+            let (mut nx, mut ny) = (x + dx, y + dy);
+            while nx >= 0 && nx < width && ny >= 0 && ny < height {
+                match cells[(ny * width + nx) as usize] {
+                    Cell::Floor if rules.neighborhood == Neighborhood::LineOfSight => {
+                        nx += dx;
+                        ny += dy;
+                    }
+                    cell => return cell == Cell::Occupied,
+                }
             }
-        }
-        if input.is_stable() {
+            false
+        };
+        let next: Vec<Cell> = (0..cells.len())
+            .map(|i| {
+                let (x, y) = (i as i32 % width, i as i32 / width);
+                let occupied = DIRECTIONS
+                    .iter()
+                    .filter(|&&(dx, dy)| sees_occupied(x, y, dx, dy))
+                    .count() as u32;
+                next_state(&rules, cells[i], occupied)
+            })
+            .collect();
+        generation += 1;
+        if next == cells {
             break;
         }
-        input.swap();
+        cells = next;
+    }
+    (cells, generation)
+}
+
+fn generate_seat_map(width: i32, height: i32, seed: u64) -> Input {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let grid = (0..width * height)
+        .map(|_| if rng.gen_range(0, 5) == 0 { b'.' } else { b'L' })
+        .collect();
+    Input {
+        width,
+        height,
+        grid,
     }
-    input.num_occupied().to_string()
+}
+
+// How a simulation ended
+#[derive(Debug, PartialEq)]
+enum Convergence {
+    // Nothing changes after this generation
+    Stable { generation: usize, occupied: usize },
+    // The state at generation start + period is the same as at start, and period is the smallest such
+    Cycle { start: usize, period: usize },
+    // Still changing, without repeating, when the generation limit was reached
+    DidNotConverge { generations: usize },
+}
+
+impl fmt::Display for Convergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Convergence::Stable {
+                generation,
+                occupied,
+            } => write!(
+                f,
+                "Stable from generation {} with {} occupied seats",
+                generation, occupied
+            ),
+            Convergence::Cycle { start, period } => write!(
+                f,
+                "Repeats every {} generations from generation {}",
+                period, start
+            ),
+            Convergence::DidNotConverge { generations } => {
+                write!(f, "Did not converge in {} generations", generations)
+            }
+        }
+    }
+}
+
+const MAX_GENERATIONS: usize = 10_000;
+
+// Generic signature for "process problem state to get an answer"
+type ProcessInputFunc = fn(&Input) -> String;
+
+fn simulate(input: &Input, rules: Rules) -> String {
+    match Automaton::new(input, rules).run(MAX_GENERATIONS) {
+        Convergence::Stable { occupied, .. } => occupied.to_string(),
+        other => other.to_string(),
+    }
+}
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Input) -> String {
+    simulate(input, PART1_RULES)
+}
+
+fn solve_part2(input: &Input) -> String {
+    simulate(input, PART2_RULES)
 }
 
 // Day-specific code to process text data into custom problem state
@@ -206,18 +702,16 @@ fn parse_input_text(input_text: &str) -> Input {
     let width = lines.first().unwrap().len() as i32;
     let height = lines.len() as i32;
     let grid = lines.join("").as_bytes().to_vec();
-    let grid_len = grid.len();
     Input {
         width,
         height,
-        grid_old: grid,
-        grid_new: vec![b'.'; grid_len],
+        grid,
     }
 }
 
 fn process_text(input_text: &str, processor: ProcessInputFunc, expected: &str) -> String {
-    let mut state = parse_input_text(input_text);
-    let actual = processor(&mut state);
+    let state = parse_input_text(input_text);
+    let actual = processor(&state);
     assert_eq!(expected, actual);
     actual
 }
@@ -228,6 +722,152 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Usage: day11 simulate [--part 1|2] [--neighborhood adjacent|sight] [--occupy N] [--vacate N]
+//                       [--alphabet .L#] [--max-generations N]
+//                       [--export DIR [--format ascii|ppm] [--scale N]] [FILE]
+fn run_simulate_tool(args: &[String]) {
+    let mut rules = PART1_RULES;
+    let mut max_generations = MAX_GENERATIONS;
+    let mut export_dir = None;
+    let mut format = ImageFormat::Ascii;
+    let mut scale = 4;
+    let mut filename = "inputs/input11.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--part" => {
+                let alphabet = rules.alphabet;
+                rules = match value().as_str() {
+                    "1" => PART1_RULES,
+                    "2" => PART2_RULES,
+                    part => panic!("Unknown part {}", part),
+                };
+                rules.alphabet = alphabet;
+            }
+            "--neighborhood" => {
+                rules.neighborhood = match value().as_str() {
+                    "adjacent" => Neighborhood::Adjacent,
+                    "sight" => Neighborhood::LineOfSight,
+                    neighborhood => panic!("Unknown neighborhood {}", neighborhood),
+                }
+            }
+            "--occupy" => rules.occupy_threshold = value().parse().unwrap(),
+            "--vacate" => rules.vacate_threshold = value().parse().unwrap(),
+            "--max-generations" => max_generations = value().parse().unwrap(),
+            "--export" => export_dir = Some(Path::new(value())),
+            "--format" => {
+                format = match value().as_str() {
+                    "ascii" => ImageFormat::Ascii,
+                    "ppm" => ImageFormat::Ppm,
+                    format => panic!("Unknown format {}", format),
+                }
+            }
+            "--scale" => scale = value().parse().unwrap(),
+            "--alphabet" => {
+                let chars = value().as_bytes();
+                assert_eq!(3, chars.len(), "Alphabet is floor, empty, occupied");
+                rules.alphabet = Alphabet {
+                    floor: chars[0],
+                    empty: chars[1],
+                    occupied: chars[2],
+                };
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let mut automaton = Automaton::new(&parse_input_text(&contents), rules);
+    let convergence = match export_dir {
+        Some(dir) => export_run(&mut automaton, max_generations, dir, format, scale),
+        None => automaton.run(max_generations),
+    };
+    println!("{}", automaton.render());
+    println!("{}", convergence);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ImageFormat {
+    Ascii,
+    Ppm,
+}
+
+// Writes every generation to DIR/frame-NNNN.txt (or .ppm), and heatmaps of how often and when each
+// seat last changed to DIR/changes.txt and DIR/last-changed.txt (or .ppm)
+fn export_run(
+    automaton: &mut Automaton,
+    max_generations: usize,
+    dir: &Path,
+    format: ImageFormat,
+    scale: usize,
+) -> Convergence {
+    fs::create_dir_all(dir).unwrap_or_else(|e| panic!("Could not create {}: {}", dir.display(), e));
+    let write = |name: String, contents: &[u8]| {
+        let path = dir.join(name);
+        fs::write(&path, contents)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+    };
+    let extension = match format {
+        ImageFormat::Ascii => "txt",
+        ImageFormat::Ppm => "ppm",
+    };
+    let mut heatmap = Heatmap::new(automaton);
+    let convergence = automaton.run_observed(max_generations, |automaton| {
+        heatmap.record(automaton);
+        let frame = match format {
+            ImageFormat::Ascii => (automaton.render() + "\n").into_bytes(),
+            ImageFormat::Ppm => automaton.render_ppm(scale),
+        };
+        write(
+            format!("frame-{:04}.{}", automaton.generation, extension),
+            &frame,
+        );
+    });
+    let (changes, last_changed) = match format {
+        ImageFormat::Ascii => (
+            (heatmap.render_changes() + "\n").into_bytes(),
+            (heatmap.render_last_changed() + "\n").into_bytes(),
+        ),
+        ImageFormat::Ppm => (heatmap.changes_ppm(scale), heatmap.last_changed_ppm(scale)),
+    };
+    write(format!("changes.{}", extension), &changes);
+    write(format!("last-changed.{}", extension), &last_changed);
+    convergence
+}
+
+// Usage: day11 bench [SIZE] [MAX_GENERATIONS]
+fn run_bench_tool(args: &[String]) {
+    let size = args.first().map_or(300, |n| n.parse().unwrap());
+    let max_generations = args.get(1).map_or(1000, |n| n.parse().unwrap());
+    let input = generate_seat_map(size, size, 2020);
+    for (name, rules) in [("part 1", PART1_RULES), ("part 2", PART2_RULES)] {
+        let start = Instant::now();
+        let (rescanned, generations) = rescan(&input, rules, max_generations);
+        let rescan_time = start.elapsed();
+        let start = Instant::now();
+        let mut automaton = Automaton::new(&input, rules);
+        automaton.run_for(max_generations);
+        let automaton_time = start.elapsed();
+        assert_eq!(rescanned, automaton.cells());
+        assert_eq!(generations, automaton.generation);
+        println!(
+            "{} on {}x{}, {} generations: rescan {:.3?}, automaton {:.3?} ({:.1}x)",
+            name,
+            size,
+            size,
+            generations,
+            rescan_time,
+            automaton_time,
+            rescan_time.as_secs_f64() / automaton_time.as_secs_f64()
+        );
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 L.LL.LL.LL
 LLLLLLL.LL
@@ -250,7 +890,225 @@ fn test_day11_part2() {
     process_text(_TEST_INPUT1, solve_part2, "26");
 }
 
+#[test]
+fn test_day11_rules() {
+    let input = parse_input_text(_TEST_INPUT1);
+    let mut automaton = Automaton::new(&input, PART1_RULES);
+    assert_eq!(71, automaton.step());
+    assert_eq!(51, automaton.step());
+    assert_eq!(
+        "\
+#.LL.L#.##
+#LLLLLL.L#
+L.L.L..L..
+#LLL.LL.L#
+#.LL.LL.LL
+#.LLLL#.##
+..L.L.....
+#LLLLLLLL#
+#.LLLLLL.L
+#.#LLLL.##",
+        automaton.render()
+    );
+
+    // Same simulation, different alphabet
+    let alphabet = Alphabet {
+        floor: b' ',
+        empty: b'o',
+        occupied: b'X',
+    };
+    let text = _TEST_INPUT1.replace('.', " ").replace('L', "o");
+    let rules = Rules {
+        alphabet,
+        ..PART2_RULES
+    };
+    assert_eq!("26", simulate(&parse_input_text(&text), rules));
+
+    // Anyone will sit next to one neighbor, but nobody ever leaves
+    let rules = Rules {
+        occupy_threshold: 1,
+        vacate_threshold: 9,
+        ..PART1_RULES
+    };
+    let mut automaton = Automaton::new(&parse_input_text("LLL\nLLL"), rules);
+    assert_eq!(
+        Convergence::Stable {
+            generation: 1,
+            occupied: 6
+        },
+        automaton.run(MAX_GENERATIONS)
+    );
+    assert_eq!("###\n###", automaton.render());
+    assert_eq!(2, automaton.generation);
+}
+
+#[test]
+fn test_day11_convergence() {
+    // Anyone will sit down alone, but nobody stays next to a neighbor
+    let rules = Rules {
+        occupy_threshold: 0,
+        vacate_threshold: 1,
+        ..PART1_RULES
+    };
+    let run = |text: &str, max_generations| {
+        Automaton::new(&parse_input_text(text), rules).run(max_generations)
+    };
+    assert_eq!(
+        Convergence::Cycle {
+            start: 0,
+            period: 2
+        },
+        run("LLL", MAX_GENERATIONS)
+    );
+    assert_eq!(
+        Convergence::Cycle {
+            start: 1,
+            period: 2
+        },
+        run("##L", MAX_GENERATIONS)
+    );
+    assert_eq!(
+        Convergence::DidNotConverge { generations: 1 },
+        run("LLL", 1)
+    );
+    assert_eq!(
+        "Repeats every 2 generations from generation 1",
+        simulate(&parse_input_text("##L"), rules)
+    );
+
+    // Zobrist hashing agrees with comparing whole states (most random maps settle, a few blink)
+    for seed in 0..10 {
+        let input = generate_seat_map(60, 60, seed);
+        let mut states = vec![Automaton::new(&input, PART1_RULES)];
+        let convergence = states[0].clone().run(500);
+        while states.len() <= 500 {
+            let mut next = states.last().unwrap().clone();
+            next.step();
+            if let Some(start) = states.iter().position(|s| s.occupied == next.occupied) {
+                let expected = if next.generation - start == 1 {
+                    Convergence::Stable {
+                        generation: start,
+                        occupied: next.count(Cell::Occupied),
+                    }
+                } else {
+                    Convergence::Cycle {
+                        start,
+                        period: next.generation - start,
+                    }
+                };
+                assert_eq!(expected, convergence);
+                break;
+            }
+            states.push(next);
+        }
+    }
+}
+
+#[test]
+fn test_day11_bitsets_match_rescan() {
+    for seed in 0..20 {
+        let input = generate_seat_map(10 + seed as i32, 7 + seed as i32 / 2, seed);
+        for rules in [PART1_RULES, PART2_RULES] {
+            let (cells, generations) = rescan(&input, rules, 200);
+            let mut automaton = Automaton::new(&input, rules);
+            automaton.run_for(200);
+            assert_eq!(cells, automaton.cells());
+            assert_eq!(generations, automaton.generation);
+        }
+    }
+    // Mostly floor, so lines of sight cross long runs of it, and wrap-around at row ends would show
+    for (width, height) in [(63, 5), (64, 9), (65, 31), (130, 3)] {
+        let mut input = generate_seat_map(width, height, width as u64);
+        for (i, c) in input.grid.iter_mut().enumerate() {
+            if i % 7 != 0 && i % 11 != 0 {
+                *c = b'.';
+            }
+        }
+        for rules in [PART1_RULES, PART2_RULES] {
+            let (cells, generations) = rescan(&input, rules, 200);
+            let mut automaton = Automaton::new(&input, rules);
+            automaton.run_for(200);
+            assert_eq!(cells, automaton.cells());
+            assert_eq!(generations, automaton.generation);
+        }
+    }
+}
+
+#[test]
+fn test_day11_frontier_matches_full_step() {
+    for seed in 0..10 {
+        let input = generate_seat_map(40 + seed as i32, 30, seed);
+        for rules in [PART1_RULES, PART2_RULES] {
+            let mut automaton = Automaton::new(&input, rules);
+            automaton.step_all();
+            for _ in 0..100 {
+                let mut frontier = automaton.clone();
+                let changes = automaton.step_all();
+                assert_eq!(changes, frontier.step_frontier());
+                assert_eq!(automaton.cells(), frontier.cells());
+                assert!(automaton.changed().eq(frontier.changed()));
+                assert!(frontier.frontier.iter().all(|&w| w == 0));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_day11_heatmap() {
+    let input = parse_input_text(_TEST_INPUT1);
+    let mut automaton = Automaton::new(&input, PART1_RULES);
+    let mut heatmap = Heatmap::new(&automaton);
+    let mut frames = vec![];
+    let mut changes_per_generation = vec![];
+    let convergence = automaton.run_observed(MAX_GENERATIONS, |automaton| {
+        heatmap.record(automaton);
+        changes_per_generation.push(automaton.changed().count());
+        frames.push(automaton.render());
+    });
+    assert_eq!(
+        Convergence::Stable {
+            generation: 5,
+            occupied: 37
+        },
+        convergence
+    );
+    assert_eq!(6, frames.len());
+    assert_eq!(_TEST_INPUT1, frames[0]);
+    assert_eq!(automaton.render(), frames[5]);
+    assert_eq!(
+        changes_per_generation.iter().sum::<usize>() as u32,
+        heatmap.changes.iter().sum::<u32>()
+    );
+    assert_eq!(&[0, 71, 51], &changes_per_generation[..3]);
+    assert_eq!(
+        "\
+1 . 3 4 . 2 1 . 1 1
+1 2 4 4 3 2 2 . 2 1
+2 . 5 . 4 . . 3 . .
+1 2 5 5 . 3 3 . 2 1
+1 . 5 4 . 2 2 . 2 2
+1 . 5 4 3 2 1 . 1 1
+. . 4 . 4 . . . . .
+1 2 3 4 5 5 4 3 2 1
+1 . 2 2 4 4 4 2 . 2
+1 . 1 2 3 4 3 . 1 1",
+        heatmap.render_changes()
+    );
+    // Every seat here flips once a generation until it settles
+    assert_eq!(heatmap.render_changes(), heatmap.render_last_changed());
+    let image = heatmap.last_changed_ppm(2);
+    assert!(image.starts_with(b"P6\n20 20\n255\n"));
+    assert_eq!(b"P6\n20 20\n255\n".len() + 20 * 20 * 3, image.len());
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("bench") => return run_bench_tool(&args[1..]),
+        Some("simulate") => return run_simulate_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input11.txt", solve_part1, "2303")
@@ -1,8 +1,13 @@
 use regex::Regex;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead};
+use std::ops::{Add, Mul, Range};
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 enum Instruction {
     N(i32),
     S(i32),
@@ -16,63 +21,460 @@ struct Input {
     instructions: Vec<Instruction>,
 }
 
-// Generic signature for "process problem state to get an answer"
-type ProcessInputFunc = fn(&Input) -> String;
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::N(arg) => write!(f, "N{}", arg),
+            Instruction::S(arg) => write!(f, "S{}", arg),
+            Instruction::E(arg) => write!(f, "E{}", arg),
+            Instruction::W(arg) => write!(f, "W{}", arg),
+            Instruction::L(arg) => write!(f, "L{}", arg),
+            Instruction::R(arg) => write!(f, "R{}", arg),
+            Instruction::F(arg) => write!(f, "F{}", arg),
+        }
+    }
+}
 
-// concrete instance of a ProcessInputFunc implementation
-#[rustfmt::skip]
-fn solve_part1(input: &Input) -> String {
-    let mut heading = 0;
-    let mut x = 0;
-    let mut y = 0;
-    for inst in input.instructions.iter() {
-        match inst {
-            Instruction::N(arg) => { y += arg; }
-            Instruction::S(arg) => { y -= arg; }
-            Instruction::E(arg) => { x += arg; }
-            Instruction::W(arg) => { x -= arg;}
-            Instruction::L(arg) => { heading = (heading + arg) % 360; }
-            Instruction::R(arg) => { heading = (heading + 360 - arg) % 360; }
-            Instruction::F(arg) => {
-                x = match heading {
-                    0 => x+arg,
-                    180 => x-arg,
-                    _ => x,
-                };
-                y = match heading {
-                    90  => y+arg,
-                    270 => y-arg,
-                    _ => y,
-                };
+// A position or direction, east and north positive. Stays in integers until it is turned by an
+// angle that isn't a multiple of 90 degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Point {
+    Exact(i64, i64),
+    Approx(f64, f64),
+}
+
+impl Point {
+    fn coords(self) -> (f64, f64) {
+        match self {
+            Point::Exact(x, y) => (x as f64, y as f64),
+            Point::Approx(x, y) => (x, y),
+        }
+    }
+    // Counterclockwise by the given number of degrees
+    fn rotated(self, degrees: i64) -> Point {
+        let degrees = degrees.rem_euclid(360);
+        match (self, degrees % 90) {
+            (ORIGIN, _) => ORIGIN,
+            (Point::Exact(x, y), 0) => match degrees {
+                0 => Point::Exact(x, y),
+                90 => Point::Exact(-y, x),
+                180 => Point::Exact(-x, -y),
+                _ => Point::Exact(y, -x),
+            },
+            // Quarter turns don't lose any precision, even for a point that is already approximate
+            (Point::Approx(x, y), 0) => match degrees {
+                0 => Point::Approx(x, y),
+                90 => Point::Approx(-y, x),
+                180 => Point::Approx(-x, -y),
+                _ => Point::Approx(y, -x),
+            },
+            _ => {
+                let (x, y) = self.coords();
+                let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+                Point::Approx(x * cos - y * sin, x * sin + y * cos)
             }
         }
     }
-    (x.abs()+y.abs()).to_string()
+    fn manhattan(self) -> String {
+        match self {
+            Point::Exact(x, y) => (x.abs() + y.abs()).to_string(),
+            Point::Approx(x, y) => format!("{:.3}", x.abs() + y.abs()),
+        }
+    }
 }
 
-#[rustfmt::skip]
-fn solve_part2(input: &Input) -> String {
-    let mut x = 0;
-    let mut y = 0;
-    let mut wx = 10;
-    let mut wy = 1;
-    for inst in input.instructions.iter() {
-        match inst {
-            Instruction::N(arg) => { wy += arg; }
-            Instruction::S(arg) => { wy -= arg; }
-            Instruction::E(arg) => { wx += arg; }
-            Instruction::W(arg) => { wx -= arg;}
-            Instruction::L(90)  | Instruction::R(270) => { let tx = wx; wx = -wy; wy = tx; }
-            Instruction::L(180) | Instruction::R(180) => { wx = -wx; wy = -wy; }
-            Instruction::L(270) | Instruction::R(90)  => { let tx = -wx; wx = wy; wy = tx; }
-            Instruction::F(arg) => {
-                x += wx*arg;
-                y += wy*arg;
+impl Add for Point {
+    type Output = Point;
+    fn add(self, other: Point) -> Point {
+        match (self, other) {
+            (Point::Exact(x1, y1), Point::Exact(x2, y2)) => Point::Exact(x1 + x2, y1 + y2),
+            _ => {
+                let ((x1, y1), (x2, y2)) = (self.coords(), other.coords());
+                Point::Approx(x1 + x2, y1 + y2)
+            }
+        }
+    }
+}
+
+impl Mul<i64> for Point {
+    type Output = Point;
+    fn mul(self, k: i64) -> Point {
+        match self {
+            Point::Exact(x, y) => Point::Exact(x * k, y * k),
+            Point::Approx(x, y) => Point::Approx(x * k as f64, y * k as f64),
+        }
+    }
+}
+
+// Complex multiplication, i.e. rotate and scale
+impl Mul<Point> for Point {
+    type Output = Point;
+    fn mul(self, other: Point) -> Point {
+        match (self, other) {
+            (Point::Exact(x1, y1), Point::Exact(x2, y2)) => {
+                Point::Exact(x1 * x2 - y1 * y2, x1 * y2 + y1 * x2)
+            }
+            _ => {
+                let ((x1, y1), (x2, y2)) = (self.coords(), other.coords());
+                Point::Approx(x1 * x2 - y1 * y2, x1 * y2 + y1 * x2)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Point::Exact(x, y) => write!(f, "({}, {})", x, y),
+            Point::Approx(x, y) => write!(f, "({:.3}, {:.3})", x, y),
+        }
+    }
+}
+
+const ORIGIN: Point = Point::Exact(0, 0);
+const UNIT: Point = Point::Exact(1, 0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Heading,  // part 1: N/S/E/W move the ship, F moves it along its heading
+    Waypoint, // part 2: N/S/E/W move the waypoint, F moves the ship towards it
+}
+
+impl Mode {
+    fn start_waypoint(self) -> Point {
+        match self {
+            Mode::Heading => UNIT,
+            Mode::Waypoint => Point::Exact(10, 1),
+        }
+    }
+}
+
+impl Instruction {
+    // What the instruction does: (move by, turn counterclockwise by, go forward this many times)
+    fn effect(self) -> (Point, i64, i64) {
+        match self {
+            Instruction::N(arg) => (Point::Exact(0, arg as i64), 0, 0),
+            Instruction::S(arg) => (Point::Exact(0, -arg as i64), 0, 0),
+            Instruction::E(arg) => (Point::Exact(arg as i64, 0), 0, 0),
+            Instruction::W(arg) => (Point::Exact(-arg as i64, 0), 0, 0),
+            Instruction::L(arg) => (ORIGIN, arg as i64, 0),
+            Instruction::R(arg) => (ORIGIN, -arg as i64, 0),
+            Instruction::F(arg) => (ORIGIN, 0, arg as i64),
+        }
+    }
+}
+
+// Where everything was after one instruction
+struct PathPoint {
+    instruction: Option<Instruction>, // None for the starting position
+    ship: Point,
+    waypoint: Point, // relative to the ship; in Heading mode, the unit heading
+}
+
+struct Voyage {
+    mode: Mode,
+    path: Vec<PathPoint>,
+}
+
+fn navigate(input: &Input, mode: Mode) -> Voyage {
+    let mut ship = ORIGIN;
+    // The waypoint is kept unturned, plus how far it has turned since, so that turns that add up to
+    // a multiple of 90 degrees (R45 then L45) leave it exact again
+    let mut waypoint = mode.start_waypoint();
+    let mut degrees = 0;
+    let mut path = vec![PathPoint {
+        instruction: None,
+        ship,
+        waypoint,
+    }];
+    for &inst in input.instructions.iter() {
+        let (offset, turn, forward) = inst.effect();
+        if forward != 0 {
+            ship = ship + waypoint.rotated(degrees) * forward;
+        }
+        match mode {
+            Mode::Heading => ship = ship + offset,
+            Mode::Waypoint if offset != ORIGIN => {
+                waypoint = waypoint.rotated(degrees) + offset;
+                degrees = 0;
+            }
+            Mode::Waypoint => {}
+        }
+        degrees = (degrees + turn).rem_euclid(360);
+        path.push(PathPoint {
+            instruction: Some(inst),
+            ship,
+            waypoint: waypoint.rotated(degrees),
+        });
+    }
+    Voyage { mode, path }
+}
+
+impl Voyage {
+    fn destination(&self) -> Point {
+        self.path.last().unwrap().ship
+    }
+    // One row per instruction, with the waypoint's absolute position in Waypoint mode
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("step,instruction,ship_x,ship_y");
+        if self.mode == Mode::Waypoint {
+            csv += ",waypoint_x,waypoint_y";
+        }
+        csv += "\n";
+        for (step, point) in self.path.iter().enumerate() {
+            let instruction = point.instruction.map_or(String::new(), |i| i.to_string());
+            let (x, y) = point.ship.coords();
+            csv += &format!("{},{},{},{}", step, instruction, x, y);
+            if self.mode == Mode::Waypoint {
+                let (wx, wy) = (point.ship + point.waypoint).coords();
+                csv += &format!(",{},{}", wx, wy);
+            }
+            csv += "\n";
+        }
+        csv
+    }
+    // North is up. The ship's track is blue and, in Waypoint mode, the waypoint's is orange.
+    fn to_svg(&self) -> String {
+        let ship: Vec<(f64, f64)> = self.path.iter().map(|p| p.ship.coords()).collect();
+        let waypoint: Vec<(f64, f64)> = match self.mode {
+            Mode::Heading => vec![],
+            Mode::Waypoint => self
+                .path
+                .iter()
+                .map(|p| (p.ship + p.waypoint).coords())
+                .collect(),
+        };
+        let all = || ship.iter().chain(waypoint.iter());
+        let min_x = all().map(|p| p.0).fold(f64::INFINITY, f64::min);
+        let max_x = all().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+        let min_y = all().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let max_y = all().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+        let margin = ((max_x - min_x).max(max_y - min_y) * 0.05).max(1.0);
+        let polyline = |points: &[(f64, f64)], color: &str| {
+            // SVG's y axis points down
+            let points: Vec<String> = points
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, 0.0 - y))
+                .collect();
+            format!(
+                "  <polyline fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" points=\"{}\"/>\n",
+                color,
+                margin / 5.0,
+                points.join(" ")
+            )
+        };
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            min_x - margin,
+            -max_y - margin,
+            max_x - min_x + 2.0 * margin,
+            max_y - min_y + 2.0 * margin
+        );
+        if !waypoint.is_empty() {
+            svg += &polyline(&waypoint, "orange");
+        }
+        svg += &polyline(&ship, "blue");
+        svg += "</svg>\n";
+        svg
+    }
+}
+
+// The net effect of a run of instructions on any starting ship and waypoint, treating points as
+// complex numbers:
+//   ship' = ship + ship_per_waypoint * waypoint + ship_offset
+//   waypoint' = waypoint turned by rotation + waypoint_offset
+// In Heading mode the waypoint is the heading, and waypoint_offset stays zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transform {
+    rotation: i64, // degrees counterclockwise, 0..360
+    ship_per_waypoint: Point,
+    ship_offset: Point,
+    waypoint_offset: Point,
+}
+
+const IDENTITY: Transform = Transform {
+    rotation: 0,
+    ship_per_waypoint: ORIGIN,
+    ship_offset: ORIGIN,
+    waypoint_offset: ORIGIN,
+};
+
+impl Transform {
+    fn from_instruction(inst: Instruction, mode: Mode) -> Transform {
+        let (offset, turn, forward) = inst.effect();
+        let mut transform = Transform {
+            rotation: turn.rem_euclid(360),
+            ship_per_waypoint: UNIT * forward,
+            ..IDENTITY
+        };
+        match mode {
+            Mode::Heading => transform.ship_offset = offset,
+            Mode::Waypoint => transform.waypoint_offset = offset,
+        }
+        transform
+    }
+    fn compose(instructions: &[Instruction], mode: Mode) -> Transform {
+        instructions.iter().fold(IDENTITY, |transform, &inst| {
+            transform.then(Transform::from_instruction(inst, mode))
+        })
+    }
+    // This transform followed by next
+    fn then(self, next: Transform) -> Transform {
+        Transform {
+            rotation: (self.rotation + next.rotation).rem_euclid(360),
+            ship_per_waypoint: self.ship_per_waypoint
+                + next.ship_per_waypoint.rotated(self.rotation),
+            ship_offset: self.ship_offset
+                + next.ship_per_waypoint * self.waypoint_offset
+                + next.ship_offset,
+            waypoint_offset: self.waypoint_offset.rotated(next.rotation) + next.waypoint_offset,
+        }
+    }
+    fn apply(self, ship: Point, waypoint: Point) -> (Point, Point) {
+        (
+            ship + self.ship_per_waypoint * waypoint + self.ship_offset,
+            waypoint.rotated(self.rotation) + self.waypoint_offset,
+        )
+    }
+    // A short instruction sequence with the same effect: at most a dozen or so instructions, however
+    // long the original was. Only possible when the transform is exact (every turn was a multiple of
+    // 90 degrees) and its numbers fit in an instruction.
+    fn simplified(self, mode: Mode) -> Option<Vec<Instruction>> {
+        if self.rotation % 90 != 0 {
+            return None;
+        }
+        let target = self.rotation;
+        let (Point::Exact(ax, ay), Point::Exact(bx, by), Point::Exact(cx, cy)) = (
+            self.ship_per_waypoint,
+            self.ship_offset,
+            self.waypoint_offset,
+        ) else {
+            return None;
+        };
+        let mut out = vec![];
+        let mut facing = 0;
+        let arg = |n: i64| i32::try_from(n.abs()).ok();
+        let translate = |out: &mut Vec<Instruction>, x: i64, y: i64| -> Option<()> {
+            match x {
+                0 => {}
+                x if x > 0 => out.push(Instruction::E(arg(x)?)),
+                x => out.push(Instruction::W(arg(x)?)),
+            }
+            match y {
+                0 => {}
+                y if y > 0 => out.push(Instruction::N(arg(y)?)),
+                y => out.push(Instruction::S(arg(y)?)),
+            }
+            Some(())
+        };
+        // Turn to face the given multiple of 90 degrees away from the original waypoint
+        let turn = |out: &mut Vec<Instruction>, facing: &mut i64, to: i64| {
+            match (to - *facing).rem_euclid(360) {
+                0 => {}
+                90 => out.push(Instruction::L(90)),
+                180 => out.push(Instruction::L(180)),
+                _ => out.push(Instruction::R(90)),
+            }
+            *facing = to;
+        };
+        match mode {
+            Mode::Heading if (cx, cy) != (0, 0) => return None,
+            Mode::Heading => translate(&mut out, bx, by)?,
+            // The ship can only move towards the waypoint, so to move it by b: go forward once with
+            // the waypoint moved by b, then once more with the waypoint turned around and moved back
+            // by b. The waypoint offsets add up to b and the original waypoint cancels out.
+            Mode::Waypoint if (bx, by) != (0, 0) => {
+                translate(&mut out, bx, by)?;
+                out.push(Instruction::F(1));
+                turn(&mut out, &mut facing, 180);
+                translate(&mut out, bx, by)?;
+                out.push(Instruction::F(1));
             }
-            _ => { panic!("Unexpected instruction {:?}", inst); }
+            Mode::Waypoint => {}
         }
+        // Then ax times towards the original waypoint, and ay times towards it turned left
+        for (k, ahead, behind) in [(ax, 0, 180), (ay, 90, 270)] {
+            if k != 0 {
+                turn(&mut out, &mut facing, if k > 0 { ahead } else { behind });
+                out.push(Instruction::F(arg(k)?));
+            }
+        }
+        turn(&mut out, &mut facing, target);
+        translate(&mut out, cx, cy)?;
+        Some(out)
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "ship' = ship + {} * waypoint + {}",
+            self.ship_per_waypoint, self.ship_offset
+        )?;
+        write!(
+            f,
+            "waypoint' = {} * waypoint + {}",
+            UNIT.rotated(self.rotation),
+            self.waypoint_offset
+        )
+    }
+}
+
+// Segment tree of instruction transforms, to compose any range of instructions in O(log n)
+struct TransformTree {
+    mode: Mode,
+    size: usize,
+    nodes: Vec<Transform>, // node i covers nodes 2i and 2i+1; leaves start at size
+}
+
+impl TransformTree {
+    fn new(instructions: &[Instruction], mode: Mode) -> TransformTree {
+        let size = instructions.len().next_power_of_two();
+        let mut nodes = vec![IDENTITY; 2 * size];
+        for (i, &inst) in instructions.iter().enumerate() {
+            nodes[size + i] = Transform::from_instruction(inst, mode);
+        }
+        for i in (1..size).rev() {
+            nodes[i] = nodes[2 * i].then(nodes[2 * i + 1]);
+        }
+        TransformTree { mode, size, nodes }
+    }
+    fn query(&self, range: Range<usize>) -> Transform {
+        let (mut lo, mut hi) = (range.start + self.size, range.end + self.size);
+        let (mut left, mut right) = (IDENTITY, IDENTITY);
+        while lo < hi {
+            if lo % 2 == 1 {
+                left = left.then(self.nodes[lo]);
+                lo += 1;
+            }
+            if hi % 2 == 1 {
+                hi -= 1;
+                right = self.nodes[hi].then(right);
+            }
+            lo /= 2;
+            hi /= 2;
+        }
+        left.then(right)
+    }
+    // Where the ship ends up after just the given instructions, from the usual starting state
+    fn ship_after(&self, range: Range<usize>) -> Point {
+        self.query(range)
+            .apply(ORIGIN, self.mode.start_waypoint())
+            .0
     }
-    (x.abs()+y.abs()).to_string()
+}
+
+// Generic signature for "process problem state to get an answer"
+type ProcessInputFunc = fn(&Input) -> String;
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Input) -> String {
+    navigate(input, Mode::Heading).destination().manhattan()
+}
+
+fn solve_part2(input: &Input) -> String {
+    navigate(input, Mode::Waypoint).destination().manhattan()
 }
 
 // Day-specific code to process text data into custom problem state
@@ -110,6 +512,94 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+fn parse_part(part: &str) -> Mode {
+    match part {
+        "1" => Mode::Heading,
+        "2" => Mode::Waypoint,
+        part => panic!("Unknown part {}", part),
+    }
+}
+
+// Usage: day12 path [--part 1|2] [--format csv|svg] [FILE]
+fn run_path_tool(args: &[String]) {
+    let mut mode = Mode::Heading;
+    let mut svg = false;
+    let mut filename = "inputs/input12.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--part" => mode = parse_part(value()),
+            "--format" => {
+                svg = match value().as_str() {
+                    "csv" => false,
+                    "svg" => true,
+                    format => panic!("Unknown format {}", format),
+                }
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let voyage = navigate(&parse_input_text(&contents), mode);
+    if svg {
+        print!("{}", voyage.to_svg());
+    } else {
+        print!("{}", voyage.to_csv());
+    }
+}
+
+// Usage: day12 compose [--part 1|2] [--ranges] [FILE]
+// Prints the net transform of the whole file and a simplified equivalent. With --ranges, also reads
+// "START END" lines from stdin and prints where the ship ends up after instructions START..END alone.
+fn run_compose_tool(args: &[String]) {
+    let mut mode = Mode::Heading;
+    let mut ranges = false;
+    let mut filename = "inputs/input12.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--part" => mode = parse_part(args.next().expect("--part needs a value")),
+            "--ranges" => ranges = true,
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let instructions = parse_input_text(&contents).instructions;
+    let transform = Transform::compose(&instructions, mode);
+    println!("{}", transform);
+    match transform.simplified(mode) {
+        Some(simplified) => {
+            let simplified: Vec<String> = simplified.iter().map(|i| i.to_string()).collect();
+            println!("Simplified: {}", simplified.join(" "));
+        }
+        None => println!("No exact simplification"),
+    }
+    if ranges {
+        let tree = TransformTree::new(&instructions, mode);
+        for line in io::stdin().lock().lines() {
+            let line = line.unwrap();
+            let bounds: Vec<usize> = line
+                .split_whitespace()
+                .map(|n| n.parse().unwrap_or_else(|_| panic!("Bad range {}", line)))
+                .collect();
+            match bounds[..] {
+                [start, end] if start <= end && end <= instructions.len() => {
+                    println!("{}..{}: {}", start, end, tree.ship_after(start..end))
+                }
+                _ => panic!("Bad range {}", line),
+            }
+        }
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 F10
 N3
@@ -117,6 +607,29 @@ F7
 R90
 F11";
 
+const _TEST_INPUT2: &str = "\
+F10
+L90
+N3
+F7
+R270
+W4
+F1
+L180
+S12
+E2
+F5
+R90
+F3
+L270
+N1
+W9
+R180
+F12
+S2
+L90
+F4";
+
 #[test]
 fn test_day12_part1() {
     process_text(_TEST_INPUT1, solve_part1, "25");
@@ -127,7 +640,135 @@ fn test_day12_part2() {
     process_text(_TEST_INPUT1, solve_part2, "286");
 }
 
+#[test]
+fn test_day12_angles() {
+    // Multiples of 90, including full turns, stay exact
+    let input = parse_input_text("L450\nF10\nR720\nL90\nF3");
+    assert_eq!(
+        Point::Exact(-3, 10),
+        navigate(&input, Mode::Heading).destination()
+    );
+
+    let input = parse_input_text("R45\nF10\nL45\nF1");
+    let Point::Approx(x, y) = navigate(&input, Mode::Heading).destination() else {
+        panic!("Expected an approximate position");
+    };
+    assert!((x - (1.0 + 50f64.sqrt())).abs() < 1e-9);
+    assert!((y + 50f64.sqrt()).abs() < 1e-9);
+    assert_eq!("15.142", solve_part1(&input));
+
+    // Turns that add up to a multiple of 90 degrees are exact again
+    let input = parse_input_text("R120\nR120\nR120\nF1");
+    assert_eq!(
+        Point::Exact(10, 1),
+        navigate(&input, Mode::Waypoint).destination()
+    );
+    let input = parse_input_text("R45\nL135\nF2");
+    assert_eq!(
+        Point::Exact(0, 2),
+        navigate(&input, Mode::Heading).destination()
+    );
+    // Moving the waypoint while it's turned by an odd angle does leave it approximate
+    let input = parse_input_text("R45\nN1\nL45\nF1");
+    assert!(matches!(
+        navigate(&input, Mode::Waypoint).destination(),
+        Point::Approx(..)
+    ));
+}
+
+#[test]
+fn test_day12_path() {
+    let voyage = navigate(&parse_input_text(_TEST_INPUT1), Mode::Waypoint);
+    assert_eq!(
+        "\
+step,instruction,ship_x,ship_y,waypoint_x,waypoint_y
+0,,0,0,10,1
+1,F10,100,10,110,11
+2,N3,100,10,110,14
+3,F7,170,38,180,42
+4,R90,170,38,174,28
+5,F11,214,-72,218,-82
+",
+        voyage.to_csv()
+    );
+    let svg = navigate(&parse_input_text(_TEST_INPUT1), Mode::Heading).to_svg();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("points=\"0,0 10,0 10,-3 17,-3 17,-3 17,8\""));
+    assert!(!svg.contains("orange"));
+}
+
+#[test]
+fn test_day12_compose() {
+    let instructions = parse_input_text(_TEST_INPUT1).instructions;
+    let transform = Transform::compose(&instructions, Mode::Waypoint);
+    assert_eq!(
+        Transform {
+            rotation: 270,
+            ship_per_waypoint: Point::Exact(17, -11),
+            ship_offset: Point::Exact(33, 21),
+            waypoint_offset: Point::Exact(3, 0),
+        },
+        transform
+    );
+    let simplified = transform.simplified(Mode::Waypoint).unwrap();
+    let simplified: Vec<String> = simplified.iter().map(|i| i.to_string()).collect();
+    assert_eq!(
+        "E33 N21 F1 L180 E33 N21 F1 L180 F17 R90 F11 E3",
+        simplified.join(" ")
+    );
+    assert_eq!(
+        Point::Exact(214, -72),
+        transform.apply(ORIGIN, Mode::Waypoint.start_waypoint()).0
+    );
+
+    // Every range from the tree matches composing it directly and replaying it, and the simplified
+    // sequence has exactly the same effect
+    let instructions = parse_input_text(_TEST_INPUT2).instructions;
+    for &mode in [Mode::Heading, Mode::Waypoint].iter() {
+        let tree = TransformTree::new(&instructions, mode);
+        for start in 0..=instructions.len() {
+            for end in start..=instructions.len() {
+                let input = Input {
+                    instructions: instructions[start..end].to_vec(),
+                };
+                let transform = Transform::compose(&input.instructions, mode);
+                assert_eq!(transform, tree.query(start..end));
+                assert_eq!(
+                    navigate(&input, mode).destination(),
+                    tree.ship_after(start..end)
+                );
+                let simplified = transform.simplified(mode).unwrap();
+                assert!(simplified.len() <= 14);
+                assert_eq!(transform, Transform::compose(&simplified, mode));
+            }
+        }
+    }
+
+    // Arbitrary angles compose too, but can't be simplified
+    let instructions = parse_input_text("R30\nF10\nL75\nN2\nF3").instructions;
+    let transform = Transform::compose(&instructions, Mode::Waypoint);
+    let input = Input { instructions };
+    let (x1, y1) = navigate(&input, Mode::Waypoint).destination().coords();
+    let (x2, y2) = transform
+        .apply(ORIGIN, Mode::Waypoint.start_waypoint())
+        .0
+        .coords();
+    assert!((x1 - x2).abs() < 1e-9 && (y1 - y2).abs() < 1e-9);
+    assert!(transform.simplified(Mode::Waypoint).is_none());
+    let instructions = parse_input_text("R30\nF10\nL30\nF3").instructions;
+    let transform = Transform::compose(&instructions, Mode::Heading);
+    assert_eq!(0, transform.rotation);
+    assert_eq!(ORIGIN, transform.waypoint_offset);
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("path") => return run_path_tool(&args[1..]),
+        Some("compose") => return run_compose_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input12.txt", solve_part1, "879")
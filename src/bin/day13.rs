@@ -1,3 +1,5 @@
+use std::env;
+use std::fmt;
 use std::fs;
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
@@ -13,7 +15,7 @@ type ProcessInputFunc = fn(&Input) -> String;
 // concrete instance of a ProcessInputFunc implementation
 #[rustfmt::skip]
 fn solve_part1(input: &Input) -> String {
-    let mut td = std::u64::MAX;
+    let mut td = u64::MAX;
     let mut first_bus_id = 0u64;
     for bus in input.bus_ids.iter() {
         match bus {
@@ -34,25 +36,178 @@ fn solve_part1(input: &Input) -> String {
     ((td-input.t0) * first_bus_id).to_string()
 }
 
-#[rustfmt::skip]
-fn solve_part2(input: &Input) -> String {
-    let id_count = input.bus_ids.len() as u64;
-    let mut stop = input.bus_ids[0].unwrap();
-    let mut prod = stop;
-    for i in 1..input.bus_ids.len() {
-        let mut n = stop + 1;
-        stop = match input.bus_ids[i] {
-            None => n,
-            Some(id) => {
-                while (n % id) != 0 {
-                    n += prod;
-                }
-                prod *= id;
-                n
-            }
+// t ≡ residue (mod modulus), with 0 <= residue < modulus
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Congruence {
+    residue: i128,
+    modulus: i128,
+}
+
+impl Congruence {
+    fn new(residue: i128, modulus: i128) -> Congruence {
+        Congruence {
+            residue: residue.rem_euclid(modulus),
+            modulus,
         }
     }
-    (stop - id_count + 1).to_string()
+}
+
+impl fmt::Display for Congruence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "t ≡ {} (mod {})", self.residue, self.modulus)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CrtError {
+    // No t satisfies both: the combination of everything before, and the next congruence
+    Inconsistent(Congruence, Congruence),
+    // The combined modulus doesn't fit in 128 bits
+    Overflow,
+}
+
+impl fmt::Display for CrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrtError::Inconsistent(a, b) => write!(f, "No solution: {} contradicts {}", b, a),
+            CrtError::Overflow => write!(f, "No solution in 128 bits"),
+        }
+    }
+}
+
+// Returns (g, x, y) with a*x + b*y = g = gcd(a, b)
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    let (mut old_r, mut r) = (a, b);
+    let (mut old_x, mut x) = (1, 0);
+    let (mut old_y, mut y) = (0, 1);
+    while r != 0 {
+        let q = old_r / r;
+        (old_r, r) = (r, old_r - q * r);
+        (old_x, x) = (x, old_x - q * x);
+        (old_y, y) = (y, old_y - q * y);
+    }
+    (old_r, old_x, old_y)
+}
+
+// a * b mod m for 0 <= a, b < m, without overflowing even when a * b doesn't fit
+fn mul_mod(a: i128, b: i128, m: i128) -> i128 {
+    if let Some(product) = a.checked_mul(b) {
+        return product % m;
+    }
+    let (mut a, mut b, mut result) = (a, b, 0);
+    while b > 0 {
+        if b & 1 == 1 {
+            result = (result + a) % m;
+        }
+        a = (a + a) % m;
+        b >>= 1;
+    }
+    result
+}
+
+// Generalized CRT: the moduli don't need to be coprime, as long as the residues agree wherever
+// the moduli share a factor
+fn combine(a: Congruence, b: Congruence) -> Result<Congruence, CrtError> {
+    let (g, p, _) = extended_gcd(a.modulus, b.modulus);
+    let difference = b.residue - a.residue;
+    if difference % g != 0 {
+        return Err(CrtError::Inconsistent(a, b));
+    }
+    // a.residue + a.modulus * k satisfies b when (a.modulus / g) * k ≡ difference / g (mod m),
+    // and p is the inverse of a.modulus / g
+    let m = b.modulus / g;
+    let k = mul_mod((difference / g).rem_euclid(m), p.rem_euclid(m), m);
+    let modulus = a.modulus.checked_mul(m).ok_or(CrtError::Overflow)?;
+    Ok(Congruence::new(a.residue + a.modulus * k, modulus))
+}
+
+fn solve_congruences(congruences: &[Congruence]) -> Result<Congruence, CrtError> {
+    congruences
+        .iter()
+        .try_fold(Congruence::new(0, 1), |combined, &next| {
+            combine(combined, next)
+        })
+}
+
+// A bus that should leave offset minutes after t (or before, if negative)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BusOffset {
+    id: u64,
+    offset: i64,
+}
+
+fn congruences(buses: &[BusOffset]) -> Vec<Congruence> {
+    buses
+        .iter()
+        .map(|bus| Congruence::new(-(bus.offset as i128), bus.id as i128))
+        .collect()
+}
+
+// Bus i in the list leaves i minutes after t
+fn bus_congruences(input: &Input) -> Vec<Congruence> {
+    let buses: Vec<BusOffset> = input
+        .bus_ids
+        .iter()
+        .enumerate()
+        .filter_map(|(i, bus)| {
+            bus.map(|id| BusOffset {
+                id,
+                offset: i as i64,
+            })
+        })
+        .collect();
+    congruences(&buses)
+}
+
+// Every t >= 0 that satisfies the solution, in order
+fn matching_timestamps(solution: Congruence) -> impl Iterator<Item = i128> {
+    std::iter::successors(Some(solution.residue), move |t| {
+        t.checked_add(solution.modulus)
+    })
+}
+
+// Parses "ID@OFFSET" entries, separated by commas or whitespace. A bare "ID" has offset 0.
+fn parse_query(query: &str) -> Result<Vec<BusOffset>, String> {
+    query
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (id, offset) = entry.split_once('@').unwrap_or((entry, "0"));
+            let id = id
+                .parse::<u64>()
+                .map_err(|_| format!("Bad bus id in {}", entry))?;
+            let offset = offset
+                .parse::<i64>()
+                .map_err(|_| format!("Bad offset in {}", entry))?;
+            if id == 0 {
+                return Err(format!("Bus id can't be 0 in {}", entry));
+            }
+            Ok(BusOffset { id, offset })
+        })
+        .collect()
+}
+
+// Every departure (time, bus id) with start <= time < end, sorted by time and then bus
+fn timetable(bus_ids: &[u64], start: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut departures: Vec<(u64, u64)> = bus_ids
+        .iter()
+        .flat_map(|&id| {
+            // Past the end of u64 means no departures in range at all
+            let first = start.div_ceil(id).checked_mul(id).unwrap_or(end);
+            (first..end)
+                .step_by(id as usize)
+                .map(move |time| (time, id))
+        })
+        .collect();
+    departures.sort_unstable();
+    departures
+}
+
+fn solve_part2(input: &Input) -> String {
+    match solve_congruences(&bus_congruences(input)) {
+        Ok(solution) => solution.residue.to_string(),
+        Err(error) => error.to_string(),
+    }
 }
 
 // Day-specific code to process text data into custom problem state
@@ -82,6 +237,49 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Usage: day13 query [--count N] ID@OFFSET...
+fn run_query_tool(args: &[String]) {
+    let mut count = 1;
+    let mut specs = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--count" => count = args.next().expect("--count needs a value").parse().unwrap(),
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => specs.push(arg.as_str()),
+        }
+    }
+    let buses = parse_query(&specs.join(",")).unwrap_or_else(|e| panic!("{}", e));
+    match solve_congruences(&congruences(&buses)) {
+        Ok(solution) => {
+            println!("{}", solution);
+            for t in matching_timestamps(solution).take(count) {
+                println!("{}", t);
+            }
+        }
+        Err(error) => println!("{}", error),
+    }
+}
+
+// Usage: day13 timetable START END [FILE]
+fn run_timetable_tool(args: &[String]) {
+    let (start, end) = match args {
+        [start, end, ..] => (start.parse().unwrap(), end.parse().unwrap()),
+        _ => panic!("Usage: day13 timetable START END [FILE]"),
+    };
+    let filename = args.get(2).map_or("inputs/input13.txt", String::as_str);
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let bus_ids: Vec<u64> = parse_input_text(&contents)
+        .bus_ids
+        .into_iter()
+        .flatten()
+        .collect();
+    for (time, id) in timetable(&bus_ids, start, end) {
+        println!("{} {}", time, id);
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 939
 7,13,x,x,59,x,31,19";
@@ -101,7 +299,82 @@ fn test_day13_part2() {
     process_text("0\n1789,37,47,1889", solve_part2, "1202161486");
 }
 
+#[test]
+fn test_day13_crt() {
+    // Moduli sharing factors, consistently and not
+    process_text("0\n4,x,6", solve_part2, "4");
+    process_text("0\n6,x,x,x,10,x,x,x,x,15", solve_part2, "6");
+    assert_eq!(
+        Err(CrtError::Inconsistent(
+            Congruence::new(0, 4),
+            Congruence::new(5, 6)
+        )),
+        solve_congruences(&bus_congruences(&parse_input_text("0\n4,6")))
+    );
+    process_text(
+        "0\n4,6",
+        solve_part2,
+        "No solution: t ≡ 5 (mod 6) contradicts t ≡ 0 (mod 4)",
+    );
+
+    // The product of these is far beyond 64 bits
+    process_text(
+        "0\n1000000007,998244353,x,1000000009",
+        solve_part2,
+        "242904923386478728803009939",
+    );
+    let huge = Congruence::new(0, 1 << 100);
+    assert_eq!(
+        Err(CrtError::Overflow),
+        solve_congruences(&[huge, Congruence::new(1, (1 << 100) + 1)])
+    );
+}
+
+#[test]
+fn test_day13_queries() {
+    let solve = |query: &str| solve_congruences(&congruences(&parse_query(query).unwrap()));
+    // The puzzle example, with its offsets spelled out and in any order
+    assert_eq!(
+        1068781,
+        solve("7@0, 13@1, 59@4, 31@6, 19@7").unwrap().residue
+    );
+    assert_eq!(1068781, solve("19@7 7 31@6 13@1 59@4").unwrap().residue);
+    // Bus 7 left a minute before t
+    let solution = solve("7@-1,13").unwrap();
+    let first: Vec<i128> = matching_timestamps(solution).take(3).collect();
+    assert_eq!(vec![78, 169, 260], first);
+    assert!(solve("4@0,6@1").is_err());
+    assert!(parse_query("7@x").is_err());
+    assert!(parse_query("0@1").is_err());
+
+    assert_eq!(
+        vec![
+            (0, 7),
+            (0, 13),
+            (7, 7),
+            (13, 13),
+            (14, 7),
+            (21, 7),
+            (26, 13),
+            (28, 7)
+        ],
+        timetable(&[7, 13], 0, 30)
+    );
+    assert_eq!(vec![(945, 7), (949, 13)], timetable(&[7, 13], 939, 950));
+    assert_eq!(
+        vec![(u64::MAX - 15, 16)],
+        timetable(&[16, 1 << 63], u64::MAX - 20, u64::MAX)
+    );
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("query") => return run_query_tool(&args[1..]),
+        Some("timetable") => return run_timetable_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input13.txt", solve_part1, "3269")
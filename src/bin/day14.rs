@@ -1,5 +1,7 @@
 use regex::Regex;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::env;
 use std::fs;
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
@@ -10,94 +12,338 @@ enum Instruction<'a> {
 }
 #[derive(Debug)]
 struct Input<'a> {
+    width: usize, // bits in a word; every mask is this long
     instructions: Vec<Instruction<'a>>,
 }
 
-// Generic signature for "process problem state to get an answer"
-type ProcessInputFunc = fn(&Input) -> String;
+// A set of addresses: the fixed bits, with every combination of the floating bits
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Pattern {
+    fixed: u64,
+    floating: u64,
+}
 
-// concrete instance of a ProcessInputFunc implementation
-#[rustfmt::skip]
-fn solve_part1(input: &Input) -> String {
-    let mut or_mask:u64 = 0;
-    let mut and_mask:u64 = u64::MAX;
-    let mut memory = HashMap::new();
-    for inst in &input.instructions {
-        match inst {
-            Instruction::Mask(m) => {
-                or_mask = 0;
-                and_mask = u64::MAX;
-                let mask_bytes = m.as_bytes();
-                for i in 0..36 {
-                    match mask_bytes[35-i] {
-                        b'0' => { and_mask ^= 1<<i; }
-                        b'1' => { or_mask |= 1<<i; }
-                        b'X' => {} // no effect
-                        _ => panic!("Malformed mask: {}", m)
-                    }
+impl Pattern {
+    fn new(fixed: u64, floating: u64) -> Pattern {
+        Pattern {
+            fixed: fixed & !floating,
+            floating,
+        }
+    }
+    fn first(&self) -> u64 {
+        self.fixed
+    }
+    // Most significant bit first, with X for floating bits
+    fn bits(&self, width: usize) -> String {
+        (0..width)
+            .rev()
+            .map(|i| match (self.floating >> i & 1, self.fixed >> i & 1) {
+                (1, _) => 'X',
+                (_, 1) => '1',
+                _ => '0',
+            })
+            .collect()
+    }
+    fn len(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+    // In ascending order, by counting through the floating bits with the fixed ones skipped
+    fn addresses(&self) -> impl Iterator<Item = u64> {
+        let (fixed, floating) = (self.fixed, self.floating);
+        std::iter::successors(Some(0), move |&bits: &u64| {
+            Some((bits | !floating).wrapping_add(1) & floating).filter(|&next| next != 0)
+        })
+        .map(move |bits| fixed | bits)
+    }
+    fn contains(&self, address: u64) -> bool {
+        (address ^ self.fixed) & !self.floating == 0
+    }
+    fn intersects(&self, other: &Pattern) -> bool {
+        (self.fixed ^ other.fixed) & !(self.floating | other.floating) == 0
+    }
+    // The addresses in self but not in other, as non-overlapping patterns
+    fn minus(&self, other: &Pattern) -> Vec<Pattern> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+        // Fix self's floating bits to other's values one at a time. Each time, the addresses with
+        // the opposite value can't be in other, and once every bit is fixed what's left is inside it.
+        let mut pieces = vec![];
+        let mut rest = *self;
+        let mut bits = self.floating & !other.floating;
+        while bits != 0 {
+            let bit = bits & bits.wrapping_neg();
+            bits ^= bit;
+            rest.floating ^= bit;
+            pieces.push(Pattern {
+                fixed: rest.fixed | (!other.fixed & bit),
+                floating: rest.floating,
+            });
+            rest.fixed |= other.fixed & bit;
+        }
+        pieces
+    }
+}
+
+// Memory as non-overlapping address patterns, each with the value stored at all of its addresses.
+// Writing splits whatever the new pattern overlaps, so no address is ever enumerated. Single
+// addresses are kept in a HashMap instead, so a chip that never floats a bit doesn't scan a list of
+// patterns on every write.
+#[derive(Clone, Default)]
+struct FloatingMemory {
+    singles: HashMap<u64, u64>,
+    cells: Vec<(Pattern, u64)>, // never overlapping singles
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: Pattern, value: u64) {
+        if !self.cells.is_empty() {
+            let mut cells = Vec::with_capacity(self.cells.len() + 1);
+            for (existing, existing_value) in self.cells.drain(..) {
+                for piece in existing.minus(&pattern) {
+                    cells.push((piece, existing_value));
                 }
             }
-            Instruction::Mem(addr,val) => {
-                let masked_val = (val | or_mask) & and_mask;
-                *memory.entry(addr).or_default() = masked_val;
+            self.cells = cells;
+        }
+        if pattern.floating == 0 {
+            self.singles.insert(pattern.first(), value);
+        } else {
+            self.singles
+                .retain(|&address, _| !pattern.contains(address));
+            self.cells.push((pattern, value));
+        }
+    }
+    // Sorted by lowest address. Patterns can interleave (0X0 is 0 and 2, 001 is 1), so this orders
+    // the patterns but not every address.
+    fn sorted(&self) -> Vec<(Pattern, u64)> {
+        let mut cells = self.cells.clone();
+        cells.extend(
+            self.singles
+                .iter()
+                .map(|(&address, &value)| (Pattern::new(address, 0), value)),
+        );
+        cells.sort_unstable_by_key(|(pattern, _)| (pattern.first(), pattern.floating));
+        cells
+    }
+    // Every address and its value, in ascending order, merging the addresses of all the patterns
+    fn addresses(&self) -> impl Iterator<Item = (u64, u64)> {
+        let mut patterns: Vec<_> = self
+            .sorted()
+            .into_iter()
+            .map(|(pattern, value)| (pattern.addresses(), value))
+            .collect();
+        let mut heap = BinaryHeap::new();
+        for (i, (addresses, _)) in patterns.iter_mut().enumerate() {
+            heap.extend(addresses.next().map(|address| Reverse((address, i))));
+        }
+        std::iter::from_fn(move || {
+            let Reverse((address, i)) = heap.pop()?;
+            let (addresses, value) = &mut patterns[i];
+            heap.extend(addresses.next().map(|next| Reverse((next, i))));
+            Some((address, *value))
+        })
+    }
+    // One line per address, so a pattern with many floating bits makes for a long dump; see
+    // csv_dump for one line per pattern
+    fn hex_dump(&self, width: usize) -> String {
+        let digits = width.div_ceil(4);
+        self.addresses()
+            .map(|(address, value)| format!("0x{:02$x}: 0x{:02$x}\n", address, value, digits))
+            .collect()
+    }
+    fn csv_dump(&self, width: usize) -> String {
+        let mut csv = String::from("pattern,first_address,addresses,value\n");
+        for (pattern, value) in self.sorted() {
+            csv += &format!(
+                "{},{},{},{}\n",
+                pattern.bits(width),
+                pattern.first(),
+                pattern.len(),
+                value
+            );
+        }
+        csv
+    }
+    fn sum(&self) -> u128 {
+        let singles: u128 = self.singles.values().map(|&value| value as u128).sum();
+        let cells: u128 = self
+            .cells
+            .iter()
+            .map(|(pattern, value)| pattern.len() * *value as u128)
+            .sum();
+        singles + cells
+    }
+}
+
+// Generic signature for "process problem state to get an answer"
+type ProcessInputFunc = fn(&Input) -> String;
+
+// A mask, split up by which bits are 0, 1 and X
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Mask {
+    zeros: u64,
+    ones: u64,
+    floating: u64,
+}
+
+impl Mask {
+    fn parse(m: &str) -> Mask {
+        let mut mask = Mask::default();
+        for (i, c) in m.bytes().rev().enumerate() {
+            match c {
+                b'0' => mask.zeros |= 1 << i,
+                b'1' => mask.ones |= 1 << i,
+                b'X' => mask.floating |= 1 << i,
+                _ => panic!("Malformed mask: {}", m),
             }
         }
+        mask
+    }
+    // 0s and 1s swapped
+    fn inverted(&self) -> Mask {
+        Mask {
+            zeros: self.ones,
+            ones: self.zeros,
+            floating: self.floating,
+        }
+    }
+    // 0 and 1 force the bit, X keeps it
+    fn apply_to_value(&self, value: u64) -> u64 {
+        (value | self.ones) & !self.zeros
+    }
+    // 0 keeps the bit, 1 forces it, X takes both values
+    fn apply_to_address(&self, addr: u64) -> Pattern {
+        Pattern::new(addr | self.ones, self.floating)
     }
-    memory.values().sum::<u64>().to_string()
 }
 
-#[rustfmt::skip]
-fn solve_part2(input: &Input) -> String {
-    let mut or_mask:u64 = 0;
-    let mut and_mask:u64 = u64::MAX;
-    let mut x_count = 0;
-    let mut x_bits = vec![0;36];
-    let mut memory = HashMap::new();
-    for inst in &input.instructions {
+// What the current mask does to each write in a docking program
+trait DecoderChip {
+    // The addresses a write of value to addr goes to, and what is stored there
+    fn decode(&self, mask: &Mask, addr: u64, value: u64) -> (Pattern, u64);
+}
+
+// Version 1: the mask applies to values
+struct ValueMasking;
+
+impl DecoderChip for ValueMasking {
+    fn decode(&self, mask: &Mask, addr: u64, value: u64) -> (Pattern, u64) {
+        (Pattern::new(addr, 0), mask.apply_to_value(value))
+    }
+}
+
+// Version 2: the mask applies to addresses, with X bits floating
+struct AddressFloating;
+
+impl DecoderChip for AddressFloating {
+    fn decode(&self, mask: &Mask, addr: u64, value: u64) -> (Pattern, u64) {
+        (mask.apply_to_address(addr), value)
+    }
+}
+
+// Both at once: addresses as in version 2, and values as in version 1
+struct AddressAndValueMasking;
+
+impl DecoderChip for AddressAndValueMasking {
+    fn decode(&self, mask: &Mask, addr: u64, value: u64) -> (Pattern, u64) {
+        (mask.apply_to_address(addr), mask.apply_to_value(value))
+    }
+}
+
+// Any chip, seeing every mask with its 0s and 1s swapped
+struct InvertedMask<C: DecoderChip>(C);
+
+impl<C: DecoderChip> DecoderChip for InvertedMask<C> {
+    fn decode(&self, mask: &Mask, addr: u64, value: u64) -> (Pattern, u64) {
+        self.0.decode(&mask.inverted(), addr, value)
+    }
+}
+
+fn chip_by_name(name: &str) -> Option<Box<dyn DecoderChip>> {
+    Some(match name {
+        "v1" => Box::new(ValueMasking),
+        "v2" => Box::new(AddressFloating),
+        "both" => Box::new(AddressAndValueMasking),
+        "inverted-v1" => Box::new(InvertedMask(ValueMasking)),
+        "inverted-v2" => Box::new(InvertedMask(AddressFloating)),
+        "inverted-both" => Box::new(InvertedMask(AddressAndValueMasking)),
+        _ => return None,
+    })
+}
+
+// One mem instruction, as written and as the chip decoded it
+struct Write {
+    line: usize,
+    addr: u64,
+    value: u64,
+    pattern: Pattern,
+    stored: u64,
+}
+
+impl Write {
+    fn describe(&self, width: usize) -> String {
+        let addresses = match self.pattern.len() {
+            1 => format!("address {}", self.pattern.first()),
+            n => format!("{} addresses {}", n, self.pattern.bits(width)),
+        };
+        format!(
+            "{}: mem[{}] = {} stores {} at {}",
+            self.line, self.addr, self.value, self.stored, addresses
+        )
+    }
+}
+
+fn run_program(input: &Input, chip: &dyn DecoderChip) -> FloatingMemory {
+    run_program_traced(input, chip, |_| {})
+}
+
+// Same as run_program, calling trace with every write
+fn run_program_traced(
+    input: &Input,
+    chip: &dyn DecoderChip,
+    mut trace: impl FnMut(&Write),
+) -> FloatingMemory {
+    let mut mask = Mask::default();
+    let mut memory = FloatingMemory::default();
+    for (line, inst) in input.instructions.iter().enumerate() {
         match inst {
-            Instruction::Mask(m) => {
-                or_mask = 0;
-                and_mask = u64::MAX;
-                x_count = 0;
-                let mask_bytes = m.as_bytes();
-                for i in 0..36 {
-                    match mask_bytes[35-i] {
-                        b'0' => {} // no effect
-                        b'1' => { or_mask |= 1<<i; }
-                        b'X' => {
-                            and_mask ^= 1<<i;
-                            x_bits[x_count] = i;
-                            x_count += 1;
-                        }
-                        _ => panic!("Malformed mask: {}", m)
-                    }
-                }
-            }
-            Instruction::Mem(addr,val) => {
-                let addr = (addr | or_mask) & and_mask;
-                let permutation_count = 1<<x_count;
-                for i in 0..permutation_count {
-                    let mut a = addr;
-                    for (ib,b) in x_bits[..x_count].iter().enumerate() {
-                        let bit = (i>>ib) & 1;
-                        a |= bit << b;
-                    }
-                    *memory.entry(a).or_default() = *val;
-                }
+            Instruction::Mask(m) => mask = Mask::parse(m),
+            Instruction::Mem(addr, val) => {
+                let (pattern, stored) = chip.decode(&mask, *addr, *val);
+                trace(&Write {
+                    line: line + 1,
+                    addr: *addr,
+                    value: *val,
+                    pattern,
+                    stored,
+                });
+                memory.write(pattern, stored);
             }
         }
     }
-    memory.values().sum::<u64>().to_string()
+    memory
+}
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Input) -> String {
+    run_program(input, &ValueMasking).sum().to_string()
+}
+
+fn solve_part2(input: &Input) -> String {
+    run_program(input, &AddressFloating).sum().to_string()
 }
 
 // Day-specific code to process text data into custom problem state
-fn parse_input_text(input_text: &str) -> Input {
+fn parse_input_text_with_width(input_text: &str, width: usize) -> Input<'_> {
+    assert!((1..=64).contains(&width), "Words must be 1 to 64 bits");
+    let fits = |n: u64| width == 64 || n >> width == 0;
     let mut instructions = Vec::new();
     let mem_re = Regex::new(r"^mem\[(?P<addr>\d+)\] = (?P<value>\d+)$").unwrap();
     for line in input_text.lines() {
         instructions.push(match &line[..2] {
             "ma" => {
-                assert_eq!(36 + 7, line.len()); // "mask = " + 36 of X/0/1
+                assert_eq!(width + 7, line.len(), "Mask isn't {} bits: {}", width, line); // "mask = " + X/0/1s
                 Instruction::Mask(&line[7..])
             }
             "me" => {
@@ -106,12 +352,25 @@ fn parse_input_text(input_text: &str) -> Input {
                     .unwrap_or_else(|| panic!("Malformed mem instruction {}", line));
                 let addr = caps.name("addr").unwrap().as_str().parse::<u64>().unwrap();
                 let val = caps.name("value").unwrap().as_str().parse::<u64>().unwrap();
+                assert!(
+                    fits(addr) && fits(val),
+                    "Doesn't fit in {} bits: {}",
+                    width,
+                    line
+                );
                 Instruction::Mem(addr, val)
             }
             _ => panic!("Malformed input line {}", line),
         })
     }
-    Input { instructions }
+    Input {
+        width,
+        instructions,
+    }
+}
+
+fn parse_input_text(input_text: &str) -> Input<'_> {
+    parse_input_text_with_width(input_text, 36)
 }
 
 fn process_text(input_text: &str, processor: ProcessInputFunc, expected: &str) -> String {
@@ -127,6 +386,48 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Usage: day14 run [--chip v1|v2|both|inverted-v1|inverted-v2|inverted-both] [--width N] [--trace]
+//                  [--dump hex|csv] [FILE]
+fn run_program_tool(args: &[String]) {
+    let mut chip = "v1";
+    let mut width = 36;
+    let mut trace = false;
+    let mut dump = None;
+    let mut filename = "inputs/input14.txt";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--chip" => chip = value(),
+            "--width" => width = value().parse().unwrap(),
+            "--trace" => trace = true,
+            "--dump" => match value().as_str() {
+                format @ ("hex" | "csv") => dump = Some(format),
+                format => panic!("Unknown dump format {}", format),
+            },
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let chip = chip_by_name(chip).unwrap_or_else(|| panic!("Unknown chip {}", chip));
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let input = parse_input_text_with_width(&contents, width);
+    let memory = run_program_traced(&input, chip.as_ref(), |write| {
+        if trace {
+            println!("{}", write.describe(input.width));
+        }
+    });
+    match dump {
+        Some("hex") => print!("{}", memory.hex_dump(input.width)),
+        Some(_) => print!("{}", memory.csv_dump(input.width)),
+        None => println!("{}", memory.sum()),
+    }
+}
+
 const _TEST_INPUT1: &str = "\
 mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X
 mem[8] = 11
@@ -149,7 +450,109 @@ fn test_day14_part2() {
     process_text(_TEST_INPUT2, solve_part2, "208");
 }
 
+#[test]
+fn test_day14_floating_memory() {
+    // Far too many addresses to enumerate, overwritten in part
+    let input = parse_input_text(
+        "\
+mask = 000000XXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 3
+mask = 00000000000000000000000000000000000X
+mem[0] = 5",
+    );
+    assert_eq!(
+        (3 * ((1u128 << 30) - 2) + 2 * 5).to_string(),
+        solve_part2(&input)
+    );
+}
+
+// Writes that overlap each other in several different ways
+const _TEST_INPUT3: &str = "\
+mask = 000000000000000000000000000000XX1X0X
+mem[5] = 7
+mask = 0000000000000000000000000000000X0X1X
+mem[40] = 3
+mem[9] = 20
+mask = 000000000000000000000000000000X1X0XX
+mem[2] = 11
+mask = 000000000000000000000000000000000000
+mem[44] = 100
+mem[9] = 5
+mask = 000000000000000000000000000000XXX001
+mem[16] = 2";
+
+#[test]
+fn test_day14_overlapping_writes() {
+    process_text(_TEST_INPUT3, solve_part2, "449");
+}
+
+#[test]
+fn test_day14_chips() {
+    let run = |text: &str, chip: &str| {
+        let input = parse_input_text(text);
+        run_program(&input, chip_by_name(chip).unwrap().as_ref())
+            .sum()
+            .to_string()
+    };
+    assert_eq!("165", run(_TEST_INPUT1, "v1"));
+    assert_eq!("208", run(_TEST_INPUT2, "v2"));
+    // Four addresses get 100 masked to 50, then eight (two of them the same) get 1
+    assert_eq!("108", run(_TEST_INPUT2, "both"));
+    // Bit 6 cleared and bit 1 set instead: 101 becomes 39, and 0 becomes 2
+    assert_eq!("41", run(_TEST_INPUT1, "inverted-v1"));
+    // Without floating bits, every write is to a single address
+    let memory = run_program(&parse_input_text(_TEST_INPUT1), &ValueMasking);
+    assert!(memory.cells.is_empty());
+    assert_eq!(2, memory.singles.len());
+    assert!(chip_by_name("v3").is_none());
+}
+
+#[test]
+fn test_day14_trace_and_dump() {
+    let input = parse_input_text(_TEST_INPUT2);
+    let mut trace = vec![];
+    let memory = run_program_traced(&input, &AddressFloating, |write| {
+        trace.push(write.describe(input.width))
+    });
+    assert_eq!(
+        vec![
+            "2: mem[42] = 100 stores 100 at 4 addresses 000000000000000000000000000000X1101X",
+            "4: mem[26] = 1 stores 1 at 8 addresses 00000000000000000000000000000001X0XX",
+        ],
+        trace
+    );
+    // The second write split the first, leaving it only 58 and 59
+    assert_eq!(
+        "\
+pattern,first_address,addresses,value
+00000000000000000000000000000001X0XX,16,8,1
+00000000000000000000000000000011101X,58,2,100
+",
+        memory.csv_dump(input.width)
+    );
+
+    let input = parse_input_text_with_width("mask = 1XXXXXX0\nmem[8] = 11\nmem[7] = 200", 8);
+    let memory = run_program(&input, &ValueMasking);
+    assert_eq!("0x07: 0xc8\n0x08: 0x8a\n", memory.hex_dump(input.width));
+    // Addresses from different patterns interleave
+    let input = parse_input_text_with_width("mask = 0X0\nmem[0] = 1\nmask = 001\nmem[0] = 2", 3);
+    let memory = run_program(&input, &AddressFloating);
+    assert_eq!(
+        "0x0: 0x1\n0x1: 0x2\n0x2: 0x1\n",
+        memory.hex_dump(input.width)
+    );
+    let memory = run_program(&parse_input_text(_TEST_INPUT2), &AddressFloating);
+    let addresses: Vec<u64> = memory.addresses().map(|(address, _)| address).collect();
+    assert_eq!(vec![16, 17, 18, 19, 24, 25, 26, 27, 58, 59], addresses);
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("run") => return run_program_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input14.txt", solve_part1, "11884151942312")
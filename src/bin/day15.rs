@@ -1,39 +1,140 @@
-use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io;
+use std::path::Path;
+
+#[path = "common/checkpoint.rs"]
+mod checkpoint;
+use checkpoint::{CheckpointReader, CheckpointWriter};
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
 #[derive(Debug)]
 struct Input {
-    numbers: Vec<u64>,
+    numbers: Vec<u32>,
 }
 
 // Generic signature for "process problem state to get an answer"
 type ProcessInputFunc = fn(&Input) -> String;
 
-fn nth_in_sequence(input: &Input, seq_len: usize) -> String {
-    let mut last_turn_spoken = HashMap::<u64, usize>::new();
-    let mut prev_last_turn_spoken = HashMap::<u64, usize>::new();
-    for (i, n) in input.numbers.iter().enumerate() {
-        last_turn_spoken.insert(*n, i); // assumes no repetition in starting numbers
+// The memory game, as an iterator over every number spoken: the starting numbers (which may
+// repeat), then each turn's age of the last number. Ends after u32::MAX turns.
+#[derive(Debug)]
+struct VanEck {
+    starting: Vec<u32>,
+    last_spoken: Vec<u32>, // turn each number was last spoken, counting from 1; 0 for never
+    turn: u32,             // turns so far
+    next: u32,             // what comes after the last number spoken
+}
+
+impl VanEck {
+    // For a game that will run about this many turns, which is also as large as any age can get
+    fn with_capacity(starting: &[u32], turns: usize) -> VanEck {
+        let largest = starting.iter().max().map_or(0, |&n| n as usize + 1);
+        VanEck {
+            starting: starting.to_vec(),
+            last_spoken: vec![0; largest.max(turns)],
+            turn: 0,
+            next: 0,
+        }
     }
-    let mut n: u64 = *input.numbers.last().unwrap();
-    for turn in input.numbers.len()..seq_len {
-        let last = *last_turn_spoken.get(&n).unwrap();
-        if !prev_last_turn_spoken.contains_key(&n) {
-            // Last turn was the first time n was spoken; next n is 0.
-            n = 0u64;
-        } else {
-            // Last turn was not the first time n was spoken
-            let prev = *prev_last_turn_spoken.get(&n).unwrap();
-            n = (last - prev) as u64;
+}
+
+impl Iterator for VanEck {
+    type Item = u32;
+    fn next(&mut self) -> Option<u32> {
+        let spoken = match self.starting.get(self.turn as usize) {
+            Some(&n) => n,
+            None => self.next,
+        };
+        self.turn = self.turn.checked_add(1)?;
+        let index = spoken as usize;
+        if index >= self.last_spoken.len() {
+            let len = (index + 1).max(self.last_spoken.len() * 2);
+            self.last_spoken.resize(len, 0);
         }
-        if last_turn_spoken.contains_key(&n) {
-            *prev_last_turn_spoken.entry(n).or_default() = *last_turn_spoken.get(&n).unwrap();
+        self.next = match self.last_spoken[index] {
+            0 => 0,
+            last => self.turn - last,
+        };
+        self.last_spoken[index] = self.turn;
+        Some(spoken)
+    }
+}
+
+// Checkpoints use the shared format in common/checkpoint.rs
+const CHECKPOINT_MAGIC: &[u8; 8] = b"AOC15VE\0";
+
+impl VanEck {
+    // Holds the whole last-spoken table, 4 bytes per number, though not the never-spoken numbers at
+    // the end of it. Late in a long game that's still about as many bytes as turns played.
+    fn to_checkpoint(&self) -> Vec<u8> {
+        let spoken = self
+            .last_spoken
+            .iter()
+            .rposition(|&t| t != 0)
+            .map_or(0, |n| n + 1);
+        let mut writer = CheckpointWriter::new(CHECKPOINT_MAGIC);
+        writer.u32(self.turn);
+        writer.u32(self.next);
+        writer.u32s(&self.starting);
+        writer.u32s(&self.last_spoken[..spoken]);
+        writer.finish()
+    }
+    fn from_checkpoint(bytes: &[u8]) -> Result<VanEck, String> {
+        let mut reader = CheckpointReader::open(bytes, CHECKPOINT_MAGIC, "day15")?;
+        let game = VanEck {
+            turn: reader.u32()?,
+            next: reader.u32()?,
+            starting: reader.u32s()?,
+            last_spoken: reader.u32s()?,
+        };
+        reader.finish()?;
+        // Things the game itself never produces
+        if game.last_spoken.iter().any(|&t| t > game.turn) {
+            return Err("Checkpoint remembers a turn after its current turn".to_string());
+        }
+        if game.next > game.turn {
+            return Err("Checkpoint's next number is older than the game".to_string());
+        }
+        Ok(game)
+    }
+    fn save(&self, path: &Path) -> io::Result<()> {
+        checkpoint::save(path, &self.to_checkpoint())
+    }
+    fn load(path: &Path) -> Result<VanEck, String> {
+        checkpoint::load(path, VanEck::from_checkpoint)
+    }
+    // The number spoken on the most recent turn
+    fn last_number(&self) -> Option<u32> {
+        if self.turn == 0 {
+            return None;
+        }
+        self.last_spoken
+            .iter()
+            .position(|&t| t == self.turn)
+            .map(|n| n as u32)
+    }
+}
+
+// Plays until the given turn, saving a checkpoint every `every` turns and at the end
+fn play_with_checkpoints(game: &mut VanEck, turns: u32, every: u32, path: &Path) -> io::Result<()> {
+    while game.turn < turns {
+        let stop = turns.min(game.turn.saturating_add(every));
+        let skip = (stop - game.turn) as usize;
+        let finished = game.nth(skip - 1).is_none();
+        game.save(path)?;
+        if finished {
+            break;
         }
-        *last_turn_spoken.entry(n).or_default() = turn;
-        //println!("{}: n={}, last_turn={:?}, prev_turn={:?}", turn+1, n, last_turn_spoken, prev_last_turn_spoken);
     }
-    n.to_string()
+    Ok(())
+}
+
+fn nth_in_sequence(input: &Input, seq_len: usize) -> String {
+    VanEck::with_capacity(&input.numbers, seq_len)
+        .nth(seq_len - 1)
+        .unwrap()
+        .to_string()
 }
 
 // concrete instance of a ProcessInputFunc implementation
@@ -52,7 +153,7 @@ fn parse_input_text(input_text: &str) -> Input {
     Input {
         numbers: input_text
             .split(',')
-            .map(|x| x.parse::<u64>().unwrap())
+            .map(|x| x.trim().parse::<u32>().unwrap())
             .collect(),
     }
 }
@@ -70,6 +171,57 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Usage: day15 run TURNS [--checkpoint FILE [--every N] [--resume]] [STARTING_NUMBERS]
+// Every checkpoint rewrites the whole last-spoken table, up to 4 bytes per turn played (about 120MB
+// by turn 30 million), so the default is to save only every 10 million turns.
+fn run_game_tool(args: &[String]) {
+    let mut turns: Option<u32> = None;
+    let mut checkpoint = None;
+    let mut every = 10_000_000;
+    let mut resume = false;
+    let mut starting = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--checkpoint" => checkpoint = Some(Path::new(value())),
+            "--every" => every = value().parse().unwrap(),
+            "--resume" => resume = true,
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ if turns.is_none() => turns = Some(arg.parse().unwrap()),
+            _ => starting = Some(arg.as_str()),
+        }
+    }
+    let turns = turns.expect("Usage: day15 run TURNS [--checkpoint FILE] [STARTING_NUMBERS]");
+    let mut game = match (checkpoint, resume) {
+        (Some(path), true) => VanEck::load(path).unwrap_or_else(|e| panic!("{}", e)),
+        (None, true) => panic!("--resume needs --checkpoint"),
+        (_, false) => {
+            let text = match starting {
+                Some(numbers) => numbers.to_string(),
+                None => fs::read_to_string("inputs/input15.txt").unwrap(),
+            };
+            VanEck::with_capacity(&parse_input_text(&text).numbers, turns as usize)
+        }
+    };
+    let start = game.turn;
+    match checkpoint {
+        Some(path) => play_with_checkpoints(&mut game, turns, every.max(1), path)
+            .unwrap_or_else(|e| panic!("Could not save {}: {}", path.display(), e)),
+        None => while game.turn < turns && game.next().is_some() {},
+    }
+    println!(
+        "Turn {}: {} (played from turn {})",
+        game.turn,
+        game.last_number()
+            .map_or("nothing".to_string(), |n| n.to_string()),
+        start
+    );
+}
+
 #[test]
 fn test_day15_part1() {
     process_text("0,3,6", solve_part1, "436");
@@ -83,17 +235,78 @@ fn test_day15_part1() {
 
 #[test]
 fn test_day15_part2() {
-    // Correct, but *slow*
-    //process_text("0,3,6", solve_part2, "175594");
-    //process_text("1,3,2", solve_part2, "2578");
-    //process_text("2,1,3", solve_part2, "3544142");
-    //process_text("1,2,3", solve_part2, "261214");
-    //process_text("2,3,1", solve_part2, "6895259");
-    //process_text("3,2,1", solve_part2, "18");
-    //process_text("3,1,2", solve_part2, "362");
+    process_text("0,3,6", solve_part2, "175594");
+    process_text("1,3,2", solve_part2, "2578");
+    process_text("2,1,3", solve_part2, "3544142");
+    process_text("1,2,3", solve_part2, "261214");
+    process_text("2,3,1", solve_part2, "6895259");
+    process_text("3,2,1", solve_part2, "18");
+    process_text("3,1,2", solve_part2, "362");
+}
+
+#[test]
+fn test_day15_sequence() {
+    let spoken: Vec<u32> = VanEck::with_capacity(&[0, 3, 6], 0).take(10).collect();
+    assert_eq!(vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0], spoken);
+    // Repeated starting numbers: the second 1 was spoken 2 turns after the first, and so on
+    let spoken: Vec<u32> = VanEck::with_capacity(&[1, 2, 1], 0).take(8).collect();
+    assert_eq!(vec![1, 2, 1, 2, 2, 1, 3, 0], spoken);
+    // The table has room for the largest starting number, even if it's more than the turns asked for
+    let game = VanEck::with_capacity(&[1000000, 7], 0);
+    assert_eq!(1000001, game.last_spoken.len());
+    let spoken: Vec<u32> = game.take(5).collect();
+    assert_eq!(vec![1000000, 7, 0, 0, 1], spoken);
+}
+
+#[test]
+fn test_day15_checkpoints() {
+    let mut game = VanEck::with_capacity(&[0, 3, 6], 0);
+    game.nth(999);
+    let saved = game.to_checkpoint();
+    let mut resumed = VanEck::from_checkpoint(&saved).unwrap();
+    assert_eq!(Some(436), resumed.nth(2020 - 1000 - 1));
+    assert_eq!(Some(436), resumed.last_number());
+    // Room set aside for numbers nobody has said yet isn't saved
+    let mut roomy = VanEck::with_capacity(&[0, 3, 6], 1_000_000);
+    roomy.nth(999);
+    assert_eq!(saved, roomy.to_checkpoint());
+
+    let mut corrupt = saved.clone();
+    corrupt[20] ^= 1;
+    assert!(VanEck::from_checkpoint(&corrupt)
+        .unwrap_err()
+        .contains("checksum"));
+    assert!(VanEck::from_checkpoint(&saved[..saved.len() / 2]).is_err());
+    assert!(VanEck::from_checkpoint(b"not a checkpoint at all")
+        .unwrap_err()
+        .contains("Not a day15"));
+    // A well-formed file from some other version
+    let mut other_version = saved[..saved.len() - 8].to_vec();
+    other_version[8..12].copy_from_slice(&2u32.to_le_bytes());
+    let hash = checkpoint::fnv1a(&other_version);
+    other_version.extend_from_slice(&hash.to_le_bytes());
+    assert!(VanEck::from_checkpoint(&other_version)
+        .unwrap_err()
+        .contains("version 2"));
+
+    // Stopping and resuming from disk gives the same answers as one uninterrupted game
+    let path = env::temp_dir().join(format!("day15-test-{}.ckpt", std::process::id()));
+    let mut game = VanEck::with_capacity(&[3, 1, 2], 0);
+    play_with_checkpoints(&mut game, 1500, 400, &path).unwrap();
+    let mut resumed = VanEck::load(&path).unwrap();
+    assert_eq!(1500, resumed.turn);
+    play_with_checkpoints(&mut resumed, 2020, 400, &path).unwrap();
+    assert_eq!(Some(1836), resumed.last_number());
+    fs::remove_file(&path).unwrap();
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("run") => return run_game_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input15.txt", solve_part1, "755")
@@ -1,4 +1,6 @@
 use regex::Regex;
+use std::collections::VecDeque;
+use std::env;
 use std::fs;
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
@@ -41,81 +43,389 @@ fn solve_part1(input: &Input) -> String {
     error_rate.to_string()
 }
 
-#[rustfmt::skip]
-fn solve_part2(input: &Input) -> String {
-    let field_count = input.fields.len();
-    let mut column_mask_for_fields:Vec<u32> = vec![(1<<field_count) - 1; field_count];
-    for ticket in input.other_tickets.iter() {
-        let mut is_valid = true;
-        for val in ticket.iter() {
-            let mut found_valid_field = false;
-            for field in input.fields.iter() {
-                if (*val >= field.min1 && *val <= field.max1) || (*val >= field.min2 && *val <= field.max2) {
-                    found_valid_field = true;
-                    break;
+impl Field<'_> {
+    fn accepts(&self, val: u32) -> bool {
+        (val >= self.min1 && val <= self.max1) || (val >= self.min2 && val <= self.max2)
+    }
+}
+
+// For each field, the columns where every valid nearby ticket has a value that field accepts.
+// Tickets with a value no field accepts are skipped entirely.
+fn candidate_columns(input: &Input) -> Vec<Vec<usize>> {
+    let valid_tickets: Vec<&Vec<u32>> = input
+        .other_tickets
+        .iter()
+        .filter(|ticket| {
+            ticket
+                .iter()
+                .all(|&val| input.fields.iter().any(|field| field.accepts(val)))
+        })
+        .collect();
+    input
+        .fields
+        .iter()
+        .map(|field| {
+            (0..input.your_ticket.len())
+                .filter(|&col| {
+                    valid_tickets
+                        .iter()
+                        .all(|ticket| field.accepts(ticket[col]))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Maximum bipartite matching of fields to candidate columns, using Hopcroft–Karp: repeatedly find
+// a maximal set of shortest augmenting paths with a BFS, then flip them with a layered DFS.
+struct Matcher<'a> {
+    candidates: &'a [Vec<usize>],
+    column_for_field: Vec<Option<usize>>,
+    field_for_column: Vec<Option<usize>>,
+    layer: Vec<usize>,
+}
+
+impl Matcher<'_> {
+    fn new(candidates: &[Vec<usize>], columns: usize) -> Matcher<'_> {
+        Matcher {
+            candidates,
+            column_for_field: vec![None; candidates.len()],
+            field_for_column: vec![None; columns],
+            layer: vec![usize::MAX; candidates.len()],
+        }
+    }
+    // Layers fields by alternating path length from the unmatched fields. Returns whether any
+    // augmenting path exists.
+    fn layer_fields(&mut self) -> bool {
+        let mut queue = VecDeque::new();
+        for (field, column) in self.column_for_field.iter().enumerate() {
+            self.layer[field] = match column {
+                None => {
+                    queue.push_back(field);
+                    0
+                }
+                Some(_) => usize::MAX,
+            };
+        }
+        let mut found = false;
+        while let Some(field) = queue.pop_front() {
+            for &col in self.candidates[field].iter() {
+                match self.field_for_column[col] {
+                    None => found = true,
+                    Some(next) if self.layer[next] == usize::MAX => {
+                        self.layer[next] = self.layer[field] + 1;
+                        queue.push_back(next);
+                    }
+                    Some(_) => {}
                 }
             }
-            if !found_valid_field {
-                //println!("Ticket {:?} is invalid; skipping", ticket);
-                is_valid = false;
-                break;
+        }
+        found
+    }
+    fn augment(&mut self, field: usize) -> bool {
+        for i in 0..self.candidates[field].len() {
+            let col = self.candidates[field][i];
+            let reachable = match self.field_for_column[col] {
+                None => true,
+                Some(next) => self.layer[next] == self.layer[field] + 1 && self.augment(next),
+            };
+            if reachable {
+                self.column_for_field[field] = Some(col);
+                self.field_for_column[col] = Some(field);
+                return true;
             }
         }
-        if is_valid {
-            // iterate again over valid tickets.
-            // TODO: Could store these masks the first time around if needed.
-            //println!("Checking ticket {:?}...", ticket);
-            for (i_val,val) in ticket.iter().enumerate() {
-                for (i_field,field) in input.fields.iter().enumerate() {
-                    if (*val >= field.min1 && *val <= field.max1) || (*val >= field.min2 && *val <= field.max2) {
-                    } else {
-                        // The value in this column failed the check for field i_field,
-                        // so field i_field can't be stored in column i_val
-                        column_mask_for_fields[i_field] &= !(1<<i_val);
-                        //println!("{} is invalid for field {}; mask={:b}", val, i_field, column_mask_for_fields[i_field]);
-                    }
+        // Dead end; don't search through this field again in this phase
+        self.layer[field] = usize::MAX;
+        false
+    }
+    fn run(mut self) -> Vec<Option<usize>> {
+        while self.layer_fields() {
+            for field in 0..self.candidates.len() {
+                if self.column_for_field[field].is_none() {
+                    self.augment(field);
                 }
             }
         }
+        self.column_for_field
     }
-    //println!("column_mask_for_fields: {:X?}", column_mask_for_fields);
+}
 
-    let mut solved_mask = 0;
-    loop {
-        let mut new_solved_mask = 0;
-        for mask in column_mask_for_fields.iter() {
-            if mask.count_ones() == 1 && (mask & solved_mask) == 0 {
-                new_solved_mask = *mask;
-                solved_mask |= mask;
-                break;
+#[derive(Debug, PartialEq)]
+enum AssignmentError {
+    // Every field needs a column of its own, and every column a field
+    FieldCount { fields: usize, columns: usize },
+    // The fields left without a column by a maximum matching
+    Unmatched(Vec<usize>),
+}
+
+// A complete field-to-column assignment, and enough structure to tell whether it's the only one.
+// Swapping columns between fields is only possible around a cycle: field A takes B's column, B
+// takes C's, ..., Z takes A's. Those cycles all lie within strongly connected components of the
+// graph with an edge from each field to the owner of every other column it could take.
+#[derive(Debug)]
+struct Assignment {
+    candidates: Vec<Vec<usize>>,
+    column_for_field: Vec<usize>,
+    field_for_column: Vec<usize>,
+    component: Vec<usize>,
+}
+
+impl Assignment {
+    fn new(candidates: &[Vec<usize>], columns: usize) -> Result<Assignment, AssignmentError> {
+        if candidates.len() != columns {
+            return Err(AssignmentError::FieldCount {
+                fields: candidates.len(),
+                columns,
+            });
+        }
+        let matched = Matcher::new(candidates, columns).run();
+        let unmatched: Vec<usize> = (0..matched.len())
+            .filter(|&field| matched[field].is_none())
+            .collect();
+        if !unmatched.is_empty() {
+            return Err(AssignmentError::Unmatched(unmatched));
+        }
+        let column_for_field: Vec<usize> = matched.into_iter().map(Option::unwrap).collect();
+        let mut field_for_column = vec![0; columns];
+        for (field, &col) in column_for_field.iter().enumerate() {
+            field_for_column[col] = field;
+        }
+        let mut assignment = Assignment {
+            candidates: candidates.to_vec(),
+            column_for_field,
+            field_for_column,
+            component: Vec::new(),
+        };
+        assignment.component = assignment.components();
+        Ok(assignment)
+    }
+    // Fields whose column this field could take instead of its own
+    fn swaps(&self, field: usize) -> impl Iterator<Item = usize> + '_ {
+        let own = self.column_for_field[field];
+        self.candidates[field]
+            .iter()
+            .filter(move |&&col| col != own)
+            .map(move |&col| self.field_for_column[col])
+    }
+    // Tarjan's strongly connected components of the swap graph, numbered per field
+    fn components(&self) -> Vec<usize> {
+        struct Tarjan {
+            index: Vec<usize>,
+            low: Vec<usize>,
+            on_stack: Vec<bool>,
+            stack: Vec<usize>,
+            next_index: usize,
+            component: Vec<usize>,
+            next_component: usize,
+        }
+        fn visit(a: &Assignment, t: &mut Tarjan, field: usize) {
+            t.index[field] = t.next_index;
+            t.low[field] = t.next_index;
+            t.next_index += 1;
+            t.stack.push(field);
+            t.on_stack[field] = true;
+            for next in a.swaps(field) {
+                if t.index[next] == usize::MAX {
+                    visit(a, t, next);
+                    t.low[field] = t.low[field].min(t.low[next]);
+                } else if t.on_stack[next] {
+                    t.low[field] = t.low[field].min(t.index[next]);
+                }
+            }
+            if t.low[field] == t.index[field] {
+                while let Some(member) = t.stack.pop() {
+                    t.on_stack[member] = false;
+                    t.component[member] = t.next_component;
+                    if member == field {
+                        break;
+                    }
+                }
+                t.next_component += 1;
             }
         }
-        if new_solved_mask == 0 {
-            break;
+        let n = self.column_for_field.len();
+        let mut t = Tarjan {
+            index: vec![usize::MAX; n],
+            low: vec![0; n],
+            on_stack: vec![false; n],
+            stack: Vec::new(),
+            next_index: 0,
+            component: vec![0; n],
+            next_component: 0,
+        };
+        for field in 0..n {
+            if t.index[field] == usize::MAX {
+                visit(self, &mut t, field);
+            }
         }
-        for mask in column_mask_for_fields.iter_mut() {
-            if *mask != new_solved_mask {
-                *mask &= !new_solved_mask;
+        t.component
+    }
+    // Every column this field has in at least one complete assignment, in ascending order
+    fn possible_columns(&self, field: usize) -> Vec<usize> {
+        let own = self.column_for_field[field];
+        self.candidates[field]
+            .iter()
+            .copied()
+            .filter(|&col| {
+                col == own || self.component[self.field_for_column[col]] == self.component[field]
+            })
+            .collect()
+    }
+    fn is_unique(&self) -> bool {
+        (0..self.column_for_field.len()).all(|field| self.possible_columns(field).len() == 1)
+    }
+    // Up to `limit` other complete assignments, each the result of moving one field to another of
+    // its possible columns and rotating the fields around the shortest cycle that allows it
+    fn alternatives(&self, limit: usize) -> Vec<Vec<usize>> {
+        let mut alternatives: Vec<Vec<usize>> = Vec::new();
+        for field in 0..self.column_for_field.len() {
+            for col in self.possible_columns(field) {
+                if alternatives.len() >= limit {
+                    return alternatives;
+                }
+                if col == self.column_for_field[field] {
+                    continue;
+                }
+                let alternative = self.rotate(field, col);
+                if !alternatives.contains(&alternative) {
+                    alternatives.push(alternative);
+                }
+            }
+        }
+        alternatives
+    }
+    // Moves `field` to `col`, and finds the shortest chain of swaps from that column's owner back
+    // round to the column `field` gave up
+    fn rotate(&self, field: usize, col: usize) -> Vec<usize> {
+        let start = self.field_for_column[col];
+        let mut came_from = vec![usize::MAX; self.column_for_field.len()];
+        came_from[start] = start;
+        let mut queue = VecDeque::from(vec![start]);
+        while let Some(current) = queue.pop_front() {
+            if current == field {
+                break;
+            }
+            for next in self.swaps(current) {
+                if came_from[next] == usize::MAX {
+                    came_from[next] = current;
+                    queue.push_back(next);
+                }
             }
         }
+        let mut columns = self.column_for_field.clone();
+        columns[field] = col;
+        let mut current = field;
+        while current != start {
+            let previous = came_from[current];
+            columns[previous] = self.column_for_field[current];
+            current = previous;
+        }
+        columns
     }
-    let column_for_fields:Vec<usize> = column_mask_for_fields.iter().map(|m| m.trailing_zeros() as usize).collect();
-    //println!("column_for_fields: {:?}", column_for_fields);
+}
+
+fn assign_fields(input: &Input) -> Result<Assignment, String> {
+    Assignment::new(&candidate_columns(input), input.your_ticket.len()).map_err(|e| match e {
+        AssignmentError::FieldCount { fields, columns } => format!(
+            "No complete assignment; {} fields but {} columns",
+            fields, columns
+        ),
+        AssignmentError::Unmatched(unmatched) => {
+            let names: Vec<&str> = unmatched.iter().map(|&f| input.fields[f].name).collect();
+            format!(
+                "No complete assignment; left without a column: {}",
+                names.join(", ")
+            )
+        }
+    })
+}
+
+fn solve_part2(input: &Input) -> String {
+    let assignment = assign_fields(input).unwrap_or_else(|e| panic!("{}", e));
     let mut prod = 1u64;
     for (i_field, field) in input.fields.iter().enumerate() {
-        let col = column_for_fields[i_field];
-        let val = input.your_ticket[col];
-        assert!((val >= field.min1 && val <= field.max1) || (val >= field.min2 && val <= field.max2));
-        if field.name.starts_with("departure") {
-            //println!("Multiplying {} (ticket[{}]={}) into prod", field.name, col, val);
-            prod *= val as u64;
+        // Our own ticket must be valid too, wherever a field's column is certain
+        let columns = assignment.possible_columns(i_field);
+        if let [col] = columns[..] {
+            let val = input.your_ticket[col];
+            assert!(
+                field.accepts(val),
+                "Your ticket's {} ({}) isn't valid",
+                field.name,
+                val
+            );
+        }
+        if !field.name.starts_with("departure") {
+            continue;
         }
+        // Other fields may be ambiguous, as long as the ones we need aren't
+        assert_eq!(
+            1,
+            columns.len(),
+            "{} could be in any of columns {:?}",
+            field.name,
+            columns
+        );
+        prod *= input.your_ticket[columns[0]] as u64;
     }
     prod.to_string()
 }
 
+// Usage: day16 assign [FILE] [--alternatives N]
+fn run_assign_tool(args: &[String]) {
+    let mut filename = "inputs/input16.txt";
+    let mut limit = 5;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--alternatives" => limit = value().parse().unwrap(),
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => filename = arg,
+        }
+    }
+    let contents =
+        fs::read_to_string(filename).unwrap_or_else(|_| panic!("Could not load {}", filename));
+    let input = parse_input_text(&contents);
+    let assignment = assign_fields(&input).unwrap_or_else(|e| panic!("{}", e));
+    for (i_field, field) in input.fields.iter().enumerate() {
+        let col = assignment.column_for_field[i_field];
+        let others: Vec<String> = assignment
+            .possible_columns(i_field)
+            .iter()
+            .filter(|&&c| c != col)
+            .map(|c| c.to_string())
+            .collect();
+        print!(
+            "{}: column {} (value {})",
+            field.name, col, input.your_ticket[col]
+        );
+        if others.is_empty() {
+            println!();
+        } else {
+            println!(", or column {}", others.join(" or "));
+        }
+    }
+    if assignment.is_unique() {
+        println!("The assignment is unique");
+        return;
+    }
+    println!("The assignment is ambiguous; some alternatives:");
+    for alternative in assignment.alternatives(limit) {
+        let moves: Vec<String> = (0..input.fields.len())
+            .filter(|&f| alternative[f] != assignment.column_for_field[f])
+            .map(|f| format!("{} -> {}", input.fields[f].name, alternative[f]))
+            .collect();
+        println!("  {}", moves.join(", "));
+    }
+}
+
 // Day-specific code to process text data into custom problem state
-fn parse_input_text(input_text: &str) -> Input {
+fn parse_input_text(input_text: &str) -> Input<'_> {
     let field_re = Regex::new(
         r"^(?P<name>[a-z ]+):\s+(?P<min1>\d+)-(?P<max1>\d+) or (?P<min2>\d+)-(?P<max2>\d+)$",
     )
@@ -237,7 +547,62 @@ fn test_day16_part2() {
     process_text(_TEST_INPUT2, solve_part2, "156");
 }
 
+#[test]
+fn test_day16_assignment() {
+    let input = parse_input_text(_TEST_INPUT2);
+    let assignment = assign_fields(&input).unwrap();
+    assert_eq!(vec![1, 0, 2], assignment.column_for_field);
+    assert!(assignment.is_unique());
+    assert!(assignment.alternatives(5).is_empty());
+
+    // More fields than fit in a u32 mask: field i can go in any column up to i, in reverse order
+    let candidates: Vec<Vec<usize>> = (0..40).map(|i| (0..=i).rev().collect()).collect();
+    let assignment = Assignment::new(&candidates, 40).unwrap();
+    assert_eq!((0..40).collect::<Vec<_>>(), assignment.column_for_field);
+    assert!(assignment.is_unique());
+
+    // Fields 0-2 rotate freely among columns 0-2; field 3 is fixed
+    let candidates = vec![vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2], vec![3]];
+    let assignment = Assignment::new(&candidates, 4).unwrap();
+    assert!(!assignment.is_unique());
+    assert_eq!(vec![0, 1, 2], assignment.possible_columns(1));
+    assert_eq!(vec![3], assignment.possible_columns(3));
+    let alternatives = assignment.alternatives(10);
+    // Each of the three swaps of two fields; rotating all three takes two moves
+    assert_eq!(3, alternatives.len());
+    for alternative in alternatives.iter() {
+        assert_ne!(&assignment.column_for_field, alternative);
+        assert_eq!(3, alternative[3]);
+        let mut used = alternative.clone();
+        used.sort_unstable();
+        assert_eq!(vec![0, 1, 2, 3], used);
+    }
+    assert_eq!(2, assignment.alternatives(2).len());
+
+    // Two fields that only fit the same column
+    let candidates = vec![vec![0], vec![0], vec![1, 2]];
+    assert_eq!(
+        AssignmentError::Unmatched(vec![1]),
+        Assignment::new(&candidates, 3).unwrap_err()
+    );
+    // More columns than fields
+    let candidates = vec![vec![0, 1], vec![1, 2]];
+    assert_eq!(
+        AssignmentError::FieldCount {
+            fields: 2,
+            columns: 3
+        },
+        Assignment::new(&candidates, 3).unwrap_err()
+    );
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("assign") => return run_assign_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input16.txt", solve_part1, "22073")
@@ -1,4 +1,11 @@
+use std::env;
 use std::fs;
+use std::io;
+use std::path::Path;
+
+#[path = "common/checkpoint.rs"]
+mod checkpoint;
+use checkpoint::{CheckpointReader, CheckpointWriter};
 
 // TODO: a trait, to mark this as "Thing That Is The Result Of Processing Input"
 #[derive(Debug)]
@@ -16,7 +23,7 @@ fn dec_and_wrap(x: u32, radix: u32) -> u32 {
     x + 1 // shift back to [1..9]
 }
 
-fn make_a_move(next_cups: &mut Vec<u32>, current_cup: u32) -> u32 {
+fn make_a_move(next_cups: &mut [u32], current_cup: u32) -> u32 {
     let held1 = next_cups[current_cup as usize];
     let held2 = next_cups[held1 as usize];
     let held3 = next_cups[held2 as usize];
@@ -32,47 +39,124 @@ fn make_a_move(next_cups: &mut Vec<u32>, current_cup: u32) -> u32 {
     next_cups[current_cup as usize]
 }
 
-// concrete instance of a ProcessInputFunc implementation
-#[rustfmt::skip]
-fn solve_part1(input: &Input) -> String {
-    let mut next_cups = vec![0u32;9+1];
-    for i in 0..9 {
-        let cup = input.cups[i];
-        next_cups[cup as usize] = input.cups[(i+1) % 9];
-    }
-    let mut current_cup = input.cups[0];
+// The circle of cups as a linked list: next_cups[c] is the cup clockwise from cup c. Index 0 is unused.
+#[derive(Debug)]
+struct CupGame {
+    next_cups: Vec<u32>,
+    current_cup: u32,
+    moves: u64,
+}
 
-    for _ in 1..101 {
-        current_cup = make_a_move(&mut next_cups, current_cup);
+impl CupGame {
+    // The labelled cups in order, then the rest of the cups up to `total` in numerical order
+    fn new(labels: &[u32], total: usize) -> CupGame {
+        let order: Vec<u32> = labels
+            .iter()
+            .copied()
+            .chain(labels.len() as u32 + 1..=total as u32)
+            .collect();
+        let mut next_cups = vec![0u32; total + 1];
+        for (i, &cup) in order.iter().enumerate() {
+            next_cups[cup as usize] = order[(i + 1) % total];
+        }
+        CupGame {
+            next_cups,
+            current_cup: order[0],
+            moves: 0,
+        }
     }
-    let mut output = 0u64;
-    let mut cup = next_cups[1];
-    for _ in 0..8 {
-        output = 10*output + (cup as u64);
-        cup = next_cups[cup as usize];
+    fn play(&mut self, moves: u64) {
+        for _ in 0..moves {
+            self.current_cup = make_a_move(&mut self.next_cups, self.current_cup);
+        }
+        self.moves += moves;
+    }
+    // The first n cups clockwise from cup 1
+    fn after_one(&self, n: usize) -> Vec<u32> {
+        std::iter::successors(Some(self.next_cups[1]), |&cup| {
+            Some(self.next_cups[cup as usize])
+        })
+        .take(n)
+        .collect()
     }
-    output.to_string()
 }
 
-#[rustfmt::skip]
-fn solve_part2(input: &Input) -> String {
-    let mut next_cups = vec![0u32;1_000_000+1];
-    for (i,next_cup) in next_cups.iter_mut().enumerate() {
-        *next_cup = (i+1) as u32;
+// Checkpoints use the shared format in common/checkpoint.rs
+const CHECKPOINT_MAGIC: &[u8; 8] = b"AOC23CU\0";
+
+impl CupGame {
+    fn to_checkpoint(&self) -> Vec<u8> {
+        let mut writer = CheckpointWriter::new(CHECKPOINT_MAGIC);
+        writer.u64(self.moves);
+        writer.u32(self.current_cup);
+        writer.u32s(&self.next_cups);
+        writer.finish()
+    }
+    fn from_checkpoint(bytes: &[u8]) -> Result<CupGame, String> {
+        let mut reader = CheckpointReader::open(bytes, CHECKPOINT_MAGIC, "day23")?;
+        let game = CupGame {
+            moves: reader.u64()?,
+            current_cup: reader.u32()?,
+            next_cups: reader.u32s()?,
+        };
+        reader.finish()?;
+        // Following the links from the current cup must visit every cup exactly once
+        let total = game.next_cups.len().saturating_sub(1);
+        let in_range = |cup: u32| cup >= 1 && cup as usize <= total;
+        if total < 5 || game.next_cups[0] != 0 || !in_range(game.current_cup) {
+            return Err("Checkpoint doesn't hold a circle of cups".to_string());
+        }
+        let mut seen = vec![false; total + 1];
+        let mut cup = game.current_cup;
+        for _ in 0..total {
+            if !in_range(cup) || seen[cup as usize] {
+                return Err("Checkpoint's cups don't form a single circle".to_string());
+            }
+            seen[cup as usize] = true;
+            cup = game.next_cups[cup as usize];
+        }
+        if cup != game.current_cup {
+            return Err("Checkpoint's cups don't form a single circle".to_string());
+        }
+        Ok(game)
+    }
+    fn save(&self, path: &Path) -> io::Result<()> {
+        checkpoint::save(path, &self.to_checkpoint())
     }
-    for i in 0..9 {
-        let cup = input.cups[i];
-        next_cups[cup as usize] = input.cups[(i+1) % 9];
+    fn load(path: &Path) -> Result<CupGame, String> {
+        checkpoint::load(path, CupGame::from_checkpoint)
     }
-    let mut current_cup = input.cups[0];
-    next_cups[1_000_000] = current_cup;
-    next_cups[input.cups[9-1] as usize] = (input.cups.len()+1) as u32;
-    for _ in 1..10_000_001 {
-        current_cup = make_a_move(&mut next_cups, current_cup);
+}
+
+// Plays until the given number of moves, saving a checkpoint every `every` moves and at the end
+fn play_with_checkpoints(
+    game: &mut CupGame,
+    moves: u64,
+    every: u64,
+    path: &Path,
+) -> io::Result<()> {
+    while game.moves < moves {
+        game.play(every.min(moves - game.moves));
+        game.save(path)?;
     }
-    let out1 = next_cups[1]as u64;
-    let out2 = next_cups[out1 as usize] as u64;
-    (out1*out2).to_string()
+    Ok(())
+}
+
+// concrete instance of a ProcessInputFunc implementation
+fn solve_part1(input: &Input) -> String {
+    let mut game = CupGame::new(&input.cups, 9);
+    game.play(100);
+    game.after_one(8)
+        .iter()
+        .map(|cup| cup.to_string())
+        .collect()
+}
+
+fn solve_part2(input: &Input) -> String {
+    let mut game = CupGame::new(&input.cups, 1_000_000);
+    game.play(10_000_000);
+    let after = game.after_one(2);
+    (after[0] as u64 * after[1] as u64).to_string()
 }
 
 // Day-specific code to process text data into custom problem state
@@ -96,6 +180,61 @@ fn process_file(filename: &str, processor: ProcessInputFunc, expected: &str) ->
     process_text(&contents, processor, expected)
 }
 
+// Usage: day23 run MOVES [--cups N] [--checkpoint FILE [--every N] [--resume]] [LABELS]
+fn run_game_tool(args: &[String]) {
+    let mut moves: Option<u64> = None;
+    let mut total = 1_000_000;
+    let mut checkpoint = None;
+    let mut every = 1_000_000;
+    let mut resume = false;
+    let mut labels = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--cups" => total = value().parse().unwrap(),
+            "--checkpoint" => checkpoint = Some(Path::new(value())),
+            "--every" => every = value().parse().unwrap(),
+            "--resume" => resume = true,
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ if moves.is_none() => moves = Some(arg.parse().unwrap()),
+            _ => labels = Some(arg.as_str()),
+        }
+    }
+    let moves = moves.expect("Usage: day23 run MOVES [--cups N] [--checkpoint FILE] [LABELS]");
+    let mut game = match (checkpoint, resume) {
+        (Some(path), true) => CupGame::load(path).unwrap_or_else(|e| panic!("{}", e)),
+        (None, true) => panic!("--resume needs --checkpoint"),
+        (_, false) => {
+            let text = match labels {
+                Some(labels) => labels.to_string(),
+                None => fs::read_to_string("inputs/input23.txt").unwrap(),
+            };
+            let cups = parse_input_text(text.trim()).cups;
+            assert!(total >= cups.len(), "Need at least {} cups", cups.len());
+            CupGame::new(&cups, total)
+        }
+    };
+    let start = game.moves;
+    match checkpoint {
+        Some(path) => play_with_checkpoints(&mut game, moves, every.max(1), path)
+            .unwrap_or_else(|e| panic!("Could not save {}: {}", path.display(), e)),
+        None => game.play(moves.saturating_sub(game.moves)),
+    }
+    let after = game.after_one(2);
+    println!(
+        "Move {}: {} and {} follow cup 1, product {} (played from move {})",
+        game.moves,
+        after[0],
+        after[1],
+        after[0] as u64 * after[1] as u64,
+        start
+    );
+}
+
 const _TEST_INPUT1: &str = "389125467";
 
 #[test]
@@ -108,7 +247,61 @@ fn test_day23_part2() {
     process_text(_TEST_INPUT1, solve_part2, "149245887792");
 }
 
+#[test]
+fn test_day23_checkpoints() {
+    let labels = parse_input_text(_TEST_INPUT1).cups;
+    let mut game = CupGame::new(&labels, 9);
+    game.play(10);
+    assert_eq!(vec![9, 2, 6, 5, 8, 3, 7, 4], game.after_one(8));
+    let saved = game.to_checkpoint();
+    let mut resumed = CupGame::from_checkpoint(&saved).unwrap();
+    resumed.play(90);
+    assert_eq!(vec![6, 7, 3, 8, 4, 5, 2, 9], resumed.after_one(8));
+
+    let mut corrupt = saved.clone();
+    corrupt[30] ^= 1;
+    assert!(CupGame::from_checkpoint(&corrupt)
+        .unwrap_err()
+        .contains("checksum"));
+    assert!(CupGame::from_checkpoint(&saved[..saved.len() - 1]).is_err());
+    // Well-formed files with bad contents: another version, and a broken circle
+    let rehash = |mut body: Vec<u8>| {
+        let hash = checkpoint::fnv1a(&body);
+        body.extend_from_slice(&hash.to_le_bytes());
+        body
+    };
+    let mut other_version = saved[..saved.len() - 8].to_vec();
+    other_version[8..12].copy_from_slice(&2u32.to_le_bytes());
+    assert!(CupGame::from_checkpoint(&rehash(other_version))
+        .unwrap_err()
+        .contains("version 2"));
+    let mut broken = game;
+    broken.next_cups[1] = 1;
+    let broken = rehash(broken.to_checkpoint()[..saved.len() - 8].to_vec());
+    assert!(CupGame::from_checkpoint(&broken)
+        .unwrap_err()
+        .contains("single circle"));
+
+    // Stopping and resuming from disk gives the same answer as one uninterrupted game
+    let path = env::temp_dir().join(format!("day23-test-{}.ckpt", std::process::id()));
+    let mut game = CupGame::new(&labels, 100);
+    play_with_checkpoints(&mut game, 700, 300, &path).unwrap();
+    let mut resumed = CupGame::load(&path).unwrap();
+    assert_eq!(700, resumed.moves);
+    play_with_checkpoints(&mut resumed, 1000, 300, &path).unwrap();
+    let mut uninterrupted = CupGame::new(&labels, 100);
+    uninterrupted.play(1000);
+    assert_eq!(uninterrupted.next_cups, resumed.next_cups);
+    fs::remove_file(&path).unwrap();
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("run") => return run_game_tool(&args[1..]),
+        Some(command) => panic!("Unknown command {}", command),
+        None => {}
+    }
     println!(
         "Part 1: {}",
         process_file("inputs/input23.txt", solve_part1, "97342568")